@@ -0,0 +1,55 @@
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+use undo::{Edit, Record};
+
+const N: usize = 10_000;
+
+struct Inc;
+
+impl Edit for Inc {
+    type Target = u64;
+    type Output = ();
+
+    fn edit(&mut self, target: &mut u64) {
+        *target += 1;
+    }
+
+    fn undo(&mut self, target: &mut u64) {
+        *target -= 1;
+    }
+}
+
+/// Returns the time it takes to undo and redo all the edits in a record with `len` entries.
+fn go_to(len: usize) -> Duration {
+    let mut target = 0;
+    let mut record = Record::<_, N>::new();
+    for _ in 0..len {
+        record.edit(&mut target, Inc);
+    }
+
+    let now = Instant::now();
+    black_box(record.go_to(&mut target, 0));
+    black_box(record.go_to(&mut target, len));
+    now.elapsed()
+}
+
+fn main() {
+    // Warm up.
+    go_to(N);
+
+    let mut per_entry = Vec::new();
+    for len in (1..=10).map(|i| i * N / 10) {
+        let elapsed = (0..10).map(|_| go_to(len)).min().unwrap();
+        let nanos = elapsed.as_nanos() as f64 / len as f64;
+        println!("go_to with {len:>5} entries: {elapsed:>10.2?} ({nanos:.2} ns/entry)");
+        per_entry.push(nanos);
+    }
+
+    // The time per entry would grow with the length if go_to was not linear.
+    let ratio = per_entry[per_entry.len() - 1] / per_entry[0];
+    println!(
+        "ratio between the time per entry at {N} and {} entries: {ratio:.2}",
+        N / 10
+    );
+    assert!(ratio < 4.0, "go_to is not linear");
+}
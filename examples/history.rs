@@ -8,7 +8,7 @@ fn custom_st_fmt<const SIZE: usize>(_: SystemTime, at: SystemTime) -> String<SIZ
     let mut result = String::<SIZE>::new();
     let dt = DateTime::<Local>::from(at);
     result
-        .write_fmt(format_args!("{}", dt.format("%H:%M:%S").to_string()))
+        .write_fmt(format_args!("{}", dt.format("%H:%M:%S")))
         .expect("enough space");
     result
 }
@@ -1,10 +1,13 @@
 use core::fmt::{self, Display, Formatter};
 use heapless::String;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
 /// This is the edit used in all the examples.
 ///
 /// Not part of the API and can change at any time.
 #[doc(hidden)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Copy, Clone, Debug, Hash, Eq, PartialEq, Ord, PartialOrd)]
 pub struct Add<const SIZE: usize>(pub char);
 
@@ -26,3 +29,18 @@ impl<const SIZE: usize> Display for Add<SIZE> {
         write!(f, "Add '{}'", self.0)
     }
 }
+
+#[cfg(any(feature = "std", feature = "binary"))]
+impl<const SIZE: usize> crate::Codec for Add<SIZE> {
+    fn encode(&self, buf: &mut [u8]) -> Option<usize> {
+        buf.get_mut(..4)?
+            .copy_from_slice(&u32::from(self.0).to_le_bytes());
+        Some(4)
+    }
+
+    fn decode(bytes: &[u8]) -> Option<(Self, usize)> {
+        let bytes = bytes.get(..4)?.try_into().ok()?;
+        let c = char::from_u32(u32::from_le_bytes(bytes))?;
+        Some((Add(c), 4))
+    }
+}
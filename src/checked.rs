@@ -0,0 +1,113 @@
+use crate::{Edit, Merged};
+use core::fmt::{self, Debug, Display, Formatter};
+
+/// Wrapper around an [`Edit`] command that verifies that undo and redo restores the target.
+///
+/// A snapshot of the target is taken before and after the edit is applied.
+/// After [`Edit::undo`] the target must be equal to the snapshot from before the edit,
+/// and after [`Edit::redo`] it must be equal to the snapshot from after the edit.
+/// This is intended to be used in tests and debug builds to find bugs in edit commands.
+///
+/// # Panics
+/// Panics if the target does not match the snapshot, reporting the edit that caused it.
+///
+/// # Examples
+/// ```
+/// # use heapless::String;
+/// # use undo::{Add, Checked, Record};
+/// let mut target = String::<256>::new();
+/// let mut record = Record::<_, 32>::new();
+/// record.edit(&mut target, Checked::new(Add('a')));
+/// record.edit(&mut target, Checked::new(Add('b')));
+/// record.undo(&mut target);
+/// record.redo(&mut target);
+/// assert_eq!(target, "ab");
+/// ```
+#[derive(Clone, Debug)]
+pub struct Checked<E: Edit> {
+    edit: E,
+    before: Option<E::Target>,
+    after: Option<E::Target>,
+}
+
+impl<E: Edit> Checked<E> {
+    /// Returns a new checked edit.
+    pub const fn new(edit: E) -> Checked<E> {
+        Checked {
+            edit,
+            before: None,
+            after: None,
+        }
+    }
+
+    /// Returns a reference to the wrapped edit.
+    pub fn get(&self) -> &E {
+        &self.edit
+    }
+
+    /// Returns a mutable reference to the wrapped edit.
+    pub fn get_mut(&mut self) -> &mut E {
+        &mut self.edit
+    }
+
+    /// Returns the wrapped edit.
+    pub fn into_inner(self) -> E {
+        self.edit
+    }
+}
+
+impl<E: Edit + Debug + Display> Edit for Checked<E>
+where
+    E::Target: Clone + PartialEq + Debug,
+{
+    type Target = E::Target;
+    type Output = E::Output;
+
+    fn edit(&mut self, target: &mut E::Target) -> E::Output {
+        self.before = Some(target.clone());
+        let output = self.edit.edit(target);
+        self.after = Some(target.clone());
+        output
+    }
+
+    fn undo(&mut self, target: &mut E::Target) -> E::Output {
+        let output = self.edit.undo(target);
+        if let Some(before) = &self.before {
+            assert_eq!(
+                target, before,
+                "undo of `{}` did not restore the target: {:?}",
+                self.edit, self.edit
+            );
+        }
+        output
+    }
+
+    fn redo(&mut self, target: &mut E::Target) -> E::Output {
+        let output = self.edit.redo(target);
+        if let Some(after) = &self.after {
+            assert_eq!(
+                target, after,
+                "redo of `{}` did not restore the target: {:?}",
+                self.edit, self.edit
+            );
+        }
+        output
+    }
+
+    fn merge(&mut self, other: Self) -> Merged<Self> {
+        match self.edit.merge(other.edit) {
+            Merged::Yes => {
+                self.after = other.after;
+                Merged::Yes
+            }
+            Merged::No(edit) => Merged::No(Checked { edit, ..other }),
+            Merged::Annul => Merged::Annul,
+        }
+    }
+}
+
+impl<E: Edit + Display> Display for Checked<E> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        Display::fmt(&self.edit, f)
+    }
+}
@@ -0,0 +1,178 @@
+#[cfg(feature = "binary")]
+use core::time::Duration;
+
+/// Encoding of values into a compact binary format.
+///
+/// Implement this for the [`Edit`](crate::Edit) commands to write them to a
+/// [`Journal`](crate::journal::Journal) or to encode the structures with the `binary` feature.
+pub trait Codec: Sized {
+    /// Encodes the value into the start of `buf`.
+    ///
+    /// Returns the number of bytes written, or `None` if `buf` is too small.
+    fn encode(&self, buf: &mut [u8]) -> Option<usize>;
+
+    /// Decodes a value from the start of `bytes`.
+    ///
+    /// Returns the value and the number of bytes read, or `None` if the bytes are invalid.
+    fn decode(bytes: &[u8]) -> Option<(Self, usize)>;
+}
+
+/// Writes values into a byte buffer, using LEB128 varints for integers.
+#[cfg(any(feature = "std", feature = "binary"))]
+pub(crate) struct Writer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+#[cfg(any(feature = "std", feature = "binary"))]
+impl<'a> Writer<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Writer<'a> {
+        Writer { buf, len: 0 }
+    }
+
+    /// Returns the number of bytes written.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn u8(&mut self, value: u8) -> Option<()> {
+        *self.buf.get_mut(self.len)? = value;
+        self.len += 1;
+        Some(())
+    }
+
+    pub fn varint(&mut self, mut value: u64) -> Option<()> {
+        while value >= 0x80 {
+            self.u8(value as u8 | 0x80)?;
+            value >>= 7;
+        }
+        self.u8(value as u8)
+    }
+
+    pub fn usize(&mut self, value: usize) -> Option<()> {
+        self.varint(value as u64)
+    }
+
+    pub fn value<T: Codec>(&mut self, value: &T) -> Option<()> {
+        self.len += value.encode(self.buf.get_mut(self.len..)?)?;
+        Some(())
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) -> Option<()> {
+        let end = self.len.checked_add(bytes.len())?;
+        self.buf.get_mut(self.len..end)?.copy_from_slice(bytes);
+        self.len = end;
+        Some(())
+    }
+
+    #[cfg(feature = "binary")]
+    pub fn option(&mut self, value: Option<usize>) -> Option<()> {
+        match value {
+            Some(value) => {
+                self.u8(1)?;
+                self.usize(value)
+            }
+            None => self.u8(0),
+        }
+    }
+
+    #[cfg(feature = "binary")]
+    pub fn duration(&mut self, duration: Duration) -> Option<()> {
+        self.varint(duration.as_secs())?;
+        self.varint(duration.subsec_nanos().into())
+    }
+}
+
+/// Reads values written by [`Writer`] from a byte slice.
+#[cfg(any(feature = "std", feature = "binary"))]
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+#[cfg(any(feature = "std", feature = "binary"))]
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Reader<'a> {
+        Reader { bytes, pos: 0 }
+    }
+
+    /// Returns `true` if all the bytes have been read.
+    #[cfg(feature = "std")]
+    pub fn is_empty(&self) -> bool {
+        self.pos == self.bytes.len()
+    }
+
+    pub fn u8(&mut self) -> Option<u8> {
+        let value = *self.bytes.get(self.pos)?;
+        self.pos += 1;
+        Some(value)
+    }
+
+    pub fn varint(&mut self) -> Option<u64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte < 0x80 {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    pub fn usize(&mut self) -> Option<usize> {
+        self.varint()?.try_into().ok()
+    }
+
+    /// Returns the number of bytes read.
+    pub fn len(&self) -> usize {
+        self.pos
+    }
+
+    pub fn value<T: Codec>(&mut self) -> Option<T> {
+        let (value, len) = T::decode(self.bytes.get(self.pos..)?)?;
+        self.pos += len;
+        Some(value)
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let end = self.pos.checked_add(len)?;
+        let bytes = self.bytes.get(self.pos..end)?;
+        self.pos = end;
+        Some(bytes)
+    }
+
+    #[cfg(feature = "binary")]
+    pub fn option(&mut self) -> Option<Option<usize>> {
+        match self.u8()? {
+            0 => Some(None),
+            1 => Some(Some(self.usize()?)),
+            _ => None,
+        }
+    }
+
+    #[cfg(feature = "binary")]
+    pub fn duration(&mut self) -> Option<Duration> {
+        let secs = self.varint()?;
+        let nanos = self.varint()?.try_into().ok()?;
+        (nanos < 1_000_000_000).then(|| Duration::new(secs, nanos))
+    }
+}
+
+/// The string is written as its length in bytes followed by its UTF-8 bytes.
+#[cfg(any(feature = "std", feature = "binary"))]
+impl<const N: usize> Codec for heapless::String<N> {
+    fn encode(&self, buf: &mut [u8]) -> Option<usize> {
+        let mut w = Writer::new(buf);
+        w.usize(self.len())?;
+        w.bytes(self.as_bytes())?;
+        Some(w.len())
+    }
+
+    fn decode(bytes: &[u8]) -> Option<(Self, usize)> {
+        let mut r = Reader::new(bytes);
+        let len = r.usize()?;
+        let string = core::str::from_utf8(r.bytes(len)?).ok()?;
+        Some((heapless::String::try_from(string).ok()?, r.len()))
+    }
+}
@@ -0,0 +1,46 @@
+//! Indexed access into a [`Deque`].
+
+use heapless::Deque;
+
+/// Random access into a [`Deque`] in constant time.
+pub(crate) trait DequeExt<T> {
+    /// Returns a reference to the element at the index.
+    fn get(&self, index: usize) -> Option<&T>;
+
+    /// Returns a mutable reference to the element at the index.
+    fn get_mut(&mut self, index: usize) -> Option<&mut T>;
+
+    /// Returns the index of the element with the key, if the elements are sorted by the key.
+    fn binary_search_by_key<K: Ord>(&self, key: K, f: impl FnMut(&T) -> K) -> Option<usize>;
+}
+
+impl<T, const N: usize> DequeExt<T> for Deque<T, N> {
+    fn get(&self, index: usize) -> Option<&T> {
+        // The elements are stored in a ring buffer that can wrap around,
+        // so the index is either in the front or back slice.
+        let (front, back) = self.as_slices();
+        match index.checked_sub(front.len()) {
+            Some(index) => back.get(index),
+            None => front.get(index),
+        }
+    }
+
+    fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        let (front, back) = self.as_mut_slices();
+        match index.checked_sub(front.len()) {
+            Some(index) => back.get_mut(index),
+            None => front.get_mut(index),
+        }
+    }
+
+    fn binary_search_by_key<K: Ord>(&self, key: K, mut f: impl FnMut(&T) -> K) -> Option<usize> {
+        let (front, back) = self.as_slices();
+        match back.first() {
+            Some(first) if f(first) <= key => back
+                .binary_search_by_key(&key, f)
+                .ok()
+                .map(|index| front.len() + index),
+            _ => front.binary_search_by_key(&key, f).ok(),
+        }
+    }
+}
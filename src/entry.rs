@@ -1,5 +1,9 @@
+#[cfg(feature = "binary")]
+use crate::codec::{Codec, Reader, Writer};
 use crate::{Edit, Merged};
 use core::fmt::{self, Debug, Display, Formatter};
+#[cfg(all(feature = "binary", not(feature = "std")))]
+use core::time::Duration;
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 #[cfg(feature = "std")]
@@ -16,6 +20,10 @@ pub struct Entry<E> {
     st_undo: SystemTime,
     #[cfg(feature = "std")]
     st_redo: SystemTime,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pinned: bool,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    id: usize,
 }
 
 impl<E> AsRef<E> for Entry<E> {
@@ -25,7 +33,7 @@ impl<E> AsRef<E> for Entry<E> {
 }
 
 impl<E> Entry<E> {
-    pub(crate) const fn new(edit: E) -> Self {
+    pub(crate) fn new(edit: E) -> Self {
         Entry {
             edit,
             #[cfg(feature = "std")]
@@ -34,9 +42,33 @@ impl<E> Entry<E> {
             st_undo: SystemTime::UNIX_EPOCH,
             #[cfg(feature = "std")]
             st_redo: SystemTime::UNIX_EPOCH,
+            pinned: false,
+            id: 0,
         }
     }
 
+    /// Returns the id of the state produced by the entry.
+    ///
+    /// The ids are given by the record, and the id is changed whenever that state might change.
+    pub(crate) fn id(&self) -> usize {
+        self.id
+    }
+
+    pub(crate) fn set_id(&mut self, id: usize) {
+        self.id = id;
+    }
+
+    /// Returns `true` if the entry is pinned.
+    ///
+    /// Pinned entries are not evicted when the limit is reached.
+    pub fn is_pinned(&self) -> bool {
+        self.pinned
+    }
+
+    pub(crate) fn set_pinned(&mut self, pinned: bool) {
+        self.pinned = pinned;
+    }
+
     /// Returns the time the edit method was called.
     #[cfg(feature = "std")]
     pub fn st_of_edit(&self) -> SystemTime {
@@ -66,6 +98,44 @@ impl<E> Entry<E> {
     }
 }
 
+impl<E> Entry<E> {
+    /// Combines the entries into a single entry using `combine`, keeping the latest times.
+    ///
+    /// The entry is pinned if any of the entries were pinned,
+    /// and it keeps the id of the last entry since it produces the same state.
+    pub(crate) fn compound<const N: usize>(
+        entries: heapless::Vec<Entry<E>, N>,
+        combine: impl FnOnce(heapless::Vec<E, N>) -> E,
+    ) -> Self {
+        let mut latest = Entry::new(());
+        let edits = entries
+            .into_iter()
+            .map(|entry| {
+                #[cfg(feature = "std")]
+                {
+                    latest.st_edit = latest.st_edit.max(entry.st_edit);
+                    latest.st_undo = latest.st_undo.max(entry.st_undo);
+                    latest.st_redo = latest.st_redo.max(entry.st_redo);
+                }
+                latest.pinned |= entry.pinned;
+                latest.id = entry.id;
+                entry.edit
+            })
+            .collect();
+        Entry {
+            edit: combine(edits),
+            #[cfg(feature = "std")]
+            st_edit: latest.st_edit,
+            #[cfg(feature = "std")]
+            st_undo: latest.st_undo,
+            #[cfg(feature = "std")]
+            st_redo: latest.st_redo,
+            pinned: latest.pinned,
+            id: latest.id,
+        }
+    }
+}
+
 impl<E: Edit> Entry<E> {
     pub(crate) fn edit(&mut self, target: &mut E::Target) -> E::Output {
         #[cfg(feature = "std")]
@@ -91,6 +161,13 @@ impl<E: Edit> Entry<E> {
         self.edit.redo(target)
     }
 
+    /// Undoes the edit, calls `f` on it, and then applies it again.
+    pub(crate) fn amend(&mut self, target: &mut E::Target, f: impl FnOnce(&mut E)) -> E::Output {
+        self.undo(target);
+        f(&mut self.edit);
+        self.edit(target)
+    }
+
     pub(crate) fn merge(&mut self, other: Self) -> Merged<Self>
     where
         Self: Sized,
@@ -103,6 +180,8 @@ impl<E: Edit> Entry<E> {
                     // when merging, so we don't store them.
                     self.st_edit = other.st_edit;
                 }
+                // The merged entry produces the same state as the other entry.
+                self.id = other.id;
                 Merged::Yes
             }
             Merged::No(edit) => Merged::No(Self { edit, ..other }),
@@ -111,6 +190,51 @@ impl<E: Edit> Entry<E> {
     }
 }
 
+/// The times are stored as the duration since [`UNIX_EPOCH`](std::time::SystemTime::UNIX_EPOCH),
+/// and are written as zero and ignored when reading without the `std` feature.
+#[cfg(feature = "binary")]
+impl<E: Codec> Codec for Entry<E> {
+    fn encode(&self, buf: &mut [u8]) -> Option<usize> {
+        let mut w = Writer::new(buf);
+        w.value(&self.edit)?;
+        #[cfg(feature = "std")]
+        for time in [self.st_edit, self.st_undo, self.st_redo] {
+            let since = time
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default();
+            w.duration(since)?;
+        }
+        #[cfg(not(feature = "std"))]
+        for _ in 0..3 {
+            w.duration(Duration::ZERO)?;
+        }
+        w.u8(self.pinned.into())?;
+        Some(w.len())
+    }
+
+    fn decode(bytes: &[u8]) -> Option<(Self, usize)> {
+        let mut r = Reader::new(bytes);
+        let mut entry = Entry::new(r.value()?);
+        let times = [r.duration()?, r.duration()?, r.duration()?];
+        #[cfg(feature = "std")]
+        {
+            let [st_edit, st_undo, st_redo] =
+                times.map(|since| SystemTime::UNIX_EPOCH.checked_add(since));
+            entry.st_edit = st_edit?;
+            entry.st_undo = st_undo?;
+            entry.st_redo = st_redo?;
+        }
+        #[cfg(not(feature = "std"))]
+        let _ = times;
+        entry.pinned = match r.u8()? {
+            0 => false,
+            1 => true,
+            _ => return None,
+        };
+        Some((entry, r.len()))
+    }
+}
+
 impl<E: Display> Display for Entry<E> {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         Display::fmt(&self.edit, f)
@@ -27,6 +27,8 @@ pub(crate) struct Format {
     pub detailed: bool,
     pub head: bool,
     pub saved: bool,
+    pub barrier: bool,
+    pub pinned: bool,
 }
 
 impl Default for Format {
@@ -37,6 +39,8 @@ impl Default for Format {
             detailed: true,
             head: true,
             saved: true,
+            barrier: true,
+            pinned: true,
         }
     }
 }
@@ -112,39 +116,56 @@ impl Format {
         at: At,
         head: At,
         saved: Option<At>,
+        barrier: Option<At>,
+        pinned: bool,
     ) -> fmt::Result {
         let at_head = self.head && at == head;
         let at_saved = self.saved && matches!(saved, Some(saved) if saved == at);
+        let at_barrier = self.barrier && matches!(barrier, Some(barrier) if barrier == at);
+        let at_pinned = self.pinned && pinned;
+
+        let mut labels = [
+            (at_head, "HEAD"),
+            (at_saved, "SAVED"),
+            (at_barrier, "BARRIER"),
+            (at_pinned, "PINNED"),
+        ]
+        .into_iter()
+        .filter_map(|(on, label)| on.then_some(label));
+        let Some(first) = labels.next() else {
+            return Ok(());
+        };
+        f.write_char(' ')?;
+        self.bracket(f, "[")?;
+        self.label(f, first)?;
+        for label in labels {
+            self.bracket(f, ",")?;
+            f.write_char(' ')?;
+            self.label(f, label)?;
+        }
+        self.bracket(f, "]")
+    }
 
-        if at_head && at_saved {
-            #[cfg(feature = "colored")]
-            if self.colored {
-                return write!(
-                    f,
-                    " {}{}{} {}{}",
-                    "[".yellow(),
-                    "HEAD".cyan(),
-                    ",".yellow(),
-                    "SAVED".green(),
-                    "]".yellow()
-                );
-            }
-            f.write_str(" [HEAD, SAVED]")
-        } else if at_head {
-            #[cfg(feature = "colored")]
-            if self.colored {
-                return write!(f, " {}{}{}", "[".yellow(), "HEAD".cyan(), "]".yellow());
-            }
-            f.write_str(" [HEAD]")
-        } else if at_saved {
-            #[cfg(feature = "colored")]
-            if self.colored {
-                return write!(f, " {}{}{}", "[".yellow(), "SAVED".green(), "]".yellow());
-            }
-            f.write_str(" [SAVED]")
-        } else {
-            Ok(())
+    fn bracket(self, f: &mut fmt::Formatter, text: &str) -> fmt::Result {
+        #[cfg(feature = "colored")]
+        if self.colored {
+            return write!(f, "{}", text.yellow());
+        }
+        f.write_str(text)
+    }
+
+    fn label(self, f: &mut fmt::Formatter, label: &str) -> fmt::Result {
+        #[cfg(feature = "colored")]
+        if self.colored {
+            let color = match label {
+                "HEAD" => Color::Cyan,
+                "SAVED" => Color::Green,
+                "BARRIER" => Color::Red,
+                _ => Color::Magenta,
+            };
+            return write!(f, "{}", label.color(color));
         }
+        f.write_str(label)
     }
 
     #[cfg(feature = "std")]
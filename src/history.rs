@@ -3,23 +3,39 @@
 mod builder;
 mod checkpoint;
 mod display;
+#[cfg(feature = "alloc")]
+mod export;
 mod queue;
 
+pub use crate::record::{QueueOutput, Savepoint};
 pub use builder::Builder;
-pub use checkpoint::Checkpoint;
+pub use checkpoint::{Checkpoint, Guard};
 pub use display::Display;
+#[cfg(feature = "alloc")]
+pub use export::{Export, ExportBranch, Header, ImportError, VERSION};
 pub use queue::Queue;
 
+#[cfg(feature = "binary")]
+use crate::codec::{Codec, Reader, Writer};
+use crate::deque::DequeExt;
+use crate::record::Removed;
 use crate::socket::Slot;
-use crate::{At, Edit, Entry, Event, Record};
+use crate::{At, Edit, EditSize, Entry, Event, Evict, Evicted, InvariantError, Keyframe, Record};
 use core::fmt;
+#[cfg(feature = "serde")]
+use core::marker::PhantomData;
 use core::mem;
+use core::ops::RangeBounds;
 use heapless::Deque;
 use heapless::String;
 use heapless::Vec;
 #[cfg(feature = "serde")]
-use serde::{Deserialize, Serialize};
+use serde::ser::SerializeStruct;
+#[cfg(feature = "serde")]
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use slab::Slab;
+#[cfg(feature = "std")]
+use std::time::SystemTime;
 
 /// A history tree of [`Edit`] commands.
 ///
@@ -31,9 +47,10 @@ use slab::Slab;
 ///
 /// # Examples
 /// ```
+/// # use heapless::String;
 /// # use undo::{Add, History};
-/// let mut target = String::new();
-/// let mut history = History::new();
+/// let mut target = String::<256>::new();
+/// let mut history = History::<_, 32>::new();
 ///
 /// history.edit(&mut target, Add('a'));
 /// history.edit(&mut target, Add('b'));
@@ -53,12 +70,17 @@ use slab::Slab;
 /// history.go_to(&mut target, abc);
 /// assert_eq!(target, "abc");
 /// ```
-#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    derive(Deserialize),
+    serde(remote = "Self", bound(deserialize = "E: Deserialize<'de>"))
+)]
 #[derive(Clone, Debug)]
-pub struct History<E, const N: usize, S = ()> {
+pub struct History<E, const N: usize, S = (), H = (), K = ()> {
     root: usize,
     saved: Option<At>,
-    record: Record<E, N, S>,
+    record: Record<E, N, S, H, K>,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "deserialize_branches"))]
     branches: Slab<Branch<E, N>>,
 }
 
@@ -69,16 +91,16 @@ impl<E, const N: usize> History<E, N> {
     }
 }
 
-impl<E, const N: usize, S> History<E, N, S> {
+impl<E, const N: usize, S, H, K> History<E, N, S, H, K> {
     /// Returns a new history builder.
-    pub fn builder() -> Builder<E, N, S> {
+    pub fn builder() -> Builder<E, N, S, H, K> {
         Builder::default()
     }
 
-    /// Reserves capacity for at least `additional` more edits.
-    ///
-    /// # Panics
-    /// Panics if the new capacity exceeds `isize::MAX` bytes.
+    // Reserves capacity for at least `additional` more edits.
+    //
+    // # Panics
+    // Panics if the new capacity exceeds `isize::MAX` bytes.
     // pub fn reserve(&mut self, additional: usize) {
     //     self.record.reserve(additional);
     // }
@@ -88,7 +110,7 @@ impl<E, const N: usize, S> History<E, N, S> {
         self.record.capacity()
     }
 
-    /// Shrinks the capacity of the history as much as possible.
+    // Shrinks the capacity of the history as much as possible.
     // pub fn shrink_to_fit(&mut self) {
     //     self.record.shrink_to_fit();
     // }
@@ -131,6 +153,24 @@ impl<E, const N: usize, S> History<E, N, S> {
             .or(self.saved)
     }
 
+    /// Returns the position of the barrier.
+    pub fn barrier(&self) -> Option<At> {
+        self.record.barrier.map(|index| At::new(self.root, index))
+    }
+
+    /// Returns the total size of the edits in the history, including the edits in the branches.
+    pub fn total_size(&self) -> usize
+    where
+        E: EditSize,
+    {
+        let branches: usize = self
+            .branches()
+            .flat_map(|(_, branch)| branch.entries())
+            .map(|entry| entry.as_ref().size())
+            .sum();
+        self.record.total_size() + branches
+    }
+
     /// Returns `true` if the history can undo.
     pub fn can_undo(&self) -> bool {
         self.record.can_undo()
@@ -190,67 +230,237 @@ impl<E, const N: usize, S> History<E, N, S> {
         self.branches.iter()
     }
 
+    /// Checks that the internal invariants of the history hold.
+    ///
+    /// This can be used to detect a corrupted history, e.g. after deserialization.
+    /// See [`Record::validate`] for more information.
+    pub fn validate(&self) -> Result<(), InvariantError> {
+        self.record.validate()?;
+        // The entries of the root branch are stored in the record and its parent is not used.
+        if !self.branches.get(self.root).is_some_and(Branch::is_empty) {
+            return Err(InvariantError::InvalidRoot);
+        }
+
+        // The state the branch forks from, or the start of the record for the root branch.
+        let start = |id: usize| {
+            if id == self.root {
+                0
+            } else {
+                self.branches[id].parent.index
+            }
+        };
+        // The end of the branch, or the end of the record for the root branch.
+        let end = |id: usize| {
+            let branch = &self.branches[id];
+            if id == self.root {
+                self.record.len()
+            } else {
+                branch.parent.index + branch.entries.len()
+            }
+        };
+        for (id, branch) in self.branches().filter(|&(id, _)| id != self.root) {
+            let parent = branch.parent;
+            if !self.branches.contains(parent.root)
+                || parent.index < start(parent.root)
+                || parent.index > end(parent.root)
+            {
+                return Err(InvariantError::InvalidParent(id));
+            }
+            // Following the parents must reach the root branch without looping.
+            let mut next = parent.root;
+            let mut steps = 0;
+            while next != self.root {
+                steps += 1;
+                if steps > self.branches.len() || !self.branches.contains(next) {
+                    return Err(InvariantError::Disconnected(id));
+                }
+                next = self.branches[next].parent.root;
+            }
+        }
+
+        if let Some(saved) = self.saved {
+            if self.record.saved.is_some() {
+                return Err(InvariantError::SavedTwice);
+            }
+            if saved.root == self.root
+                || !self.branches.contains(saved.root)
+                || saved.index < start(saved.root)
+                || saved.index > end(saved.root)
+            {
+                return Err(InvariantError::SavedOutOfBounds);
+            }
+        }
+        Ok(())
+    }
+
+    /// Pins the entry at the index in the current root branch.
+    ///
+    /// See [`Record::pin`] for more information.
+    pub fn pin(&mut self, index: usize) -> bool {
+        self.record.pin(index)
+    }
+
+    /// Unpins the entry at the index in the current root branch.
+    pub fn unpin(&mut self, index: usize) -> bool {
+        self.record.unpin(index)
+    }
+
     /// Returns a queue.
-    pub fn queue<const M: usize>(&mut self) -> Queue<E, N, M, S> {
+    pub fn queue<const M: usize>(&mut self) -> Queue<'_, E, N, M, S, H, K> {
         Queue::from(self)
     }
 
     /// Returns a checkpoint.
-    pub fn checkpoint<const M: usize>(&mut self) -> Checkpoint<E, N, M, S> {
+    pub fn checkpoint<const M: usize>(&mut self) -> Checkpoint<'_, E, N, M, S, H, K> {
         Checkpoint::from(self)
     }
 
     /// Returns a structure for configurable formatting of the history.
-    pub fn display<const SIZE: usize>(&self) -> Display<E, N, SIZE, S> {
+    pub fn display<const SIZE: usize>(&self) -> Display<'_, E, N, SIZE, S, H, K> {
         Display::from(self)
     }
 
+    /// Returns the keyframes if they are set.
+    pub fn keyframes(&self) -> Option<&K> {
+        self.record.keyframes()
+    }
+
+    /// Gives the entries new ids in order, since the ids are not serialized.
+    ///
+    /// The entries in a branch are given ids after the state the branch was split off from.
+    #[cfg(any(feature = "alloc", feature = "serde", feature = "binary"))]
+    pub(crate) fn renew_ids(&mut self) {
+        self.record.renew_ids();
+        for (id, branch) in self.branches.iter_mut() {
+            if id != self.root {
+                for entry in branch.entries.iter_mut() {
+                    entry.set_id(self.record.new_id());
+                }
+            }
+        }
+    }
+
+    /// Returns the ids of the branches that were split off from the branch with the given id.
+    fn children_of(&self, id: usize) -> impl Iterator<Item = usize> + '_ {
+        // The parent of the root branch is not used so it must be skipped.
+        self.branches()
+            .filter(move |&(child, branch)| child != self.root && branch.parent.root == id)
+            .map(|(child, _)| child)
+    }
+
+    /// Returns the index in the root branch where the branch was split off.
+    fn fork_of(&self, mut id: usize) -> usize {
+        loop {
+            let parent = self.branches[id].parent;
+            if parent.root == self.root {
+                break parent.index;
+            }
+            id = parent.root;
+        }
+    }
+
+    /// Returns the ids of the branches on the path from the root branch to the branch.
+    fn mk_path(&self, mut to: usize) -> Option<Vec<usize, N>> {
+        debug_assert_ne!(self.root, to);
+        let mut path = Vec::<_, N>::new();
+        while to != self.root {
+            path.push(to).ok()?;
+            to = self.branches.get(to)?.parent.root;
+        }
+        path.as_mut_slice().reverse();
+        Some(path)
+    }
+}
+
+impl<E, const N: usize, S, H: Evict<E, N>, K> History<E, N, S, H, K> {
     fn rm_child_of(&mut self, at: At) {
         // We need to check if any of the branches had the removed node as root.
         let mut dead: Vec<_, N> = self
-            .branches()
-            .filter(|&(_, child)| child.parent == at)
-            .map(|(id, _)| id)
+            .children_of(at.root)
+            .filter(|&id| self.branches[id].parent == at)
             .collect();
         while let Some(id) = dead.pop() {
             // Remove the dead branch.
-            self.branches.remove(id);
+            let branch = self.branches.remove(id);
+            self.record.evict(Evicted::Branch(branch));
             self.saved = self.saved.filter(|s| s.root != id);
             // Add the children of the dead branch so they are removed too.
-            dead.extend(
-                self.branches()
-                    .filter(|&(_, child)| child.parent.root == id)
-                    .map(|(id, _)| id),
-            )
+            dead.extend(self.children_of(id))
         }
     }
 
-    fn mk_path(&mut self, mut to: usize) -> Option<impl Iterator<Item = (usize, Branch<E, N>)>> {
-        debug_assert_ne!(self.root, to);
-        let mut dest = self.nil_replace(to)?;
+    /// Removes the branches that were split off from the removed states in the root branch
+    /// and moves the branches after them.
+    fn rm_states(&mut self, removed: Removed) {
+        let (first, last, by) = match removed {
+            Removed::Front(by) => (0, by - 1, by),
+            Removed::At { at, by } => (at, at, by),
+        };
+        for index in first..=last {
+            self.rm_child_of(At::new(self.root, index));
+        }
+        self.shift_after(last, by);
+    }
 
-        let mut i = dest.parent.root;
-        let mut path = Vec::<_, N>::new();
-        let _ = path.push((to, dest));
-        while i != self.root {
-            dest = self.nil_replace(i).unwrap();
-            to = i;
-            i = dest.parent.root;
-            let _ = path.push((to, dest));
+    /// Shifts the states after `index` in the root branch `by` steps towards the start.
+    fn shift_after(&mut self, index: usize, by: usize) {
+        if by == 0 {
+            return;
+        }
+        if let Some(saved) = self.saved.filter(|saved| self.fork_of(saved.root) > index) {
+            self.saved = Some(At::new(saved.root, saved.index - by));
         }
+        // The indices of a branch are relative to the start of the root branch,
+        // so all the children of the moved branches needs to be moved too.
+        let mut moved: Vec<_, N> = self
+            .children_of(self.root)
+            .filter(|&id| self.branches[id].parent.index > index)
+            .collect();
+        while let Some(id) = moved.pop() {
+            self.branches[id].parent.index -= by;
+            moved.extend(self.children_of(id))
+        }
+    }
 
-        path.as_mut_slice().reverse();
-        Some(path.into_iter())
+    /// Removes the branches without children where all the edits are older than `time`.
+    #[cfg(feature = "std")]
+    fn rm_branches_before(&mut self, time: SystemTime) {
+        // Removing a branch can make its parent a leaf, so we repeat until none are found.
+        loop {
+            let expired = self
+                .branches()
+                .filter(|&(id, _)| id != self.root && self.children_of(id).next().is_none())
+                .find(|(_, branch)| branch.entries().all(|entry| entry.st_of_edit() < time))
+                .map(|(id, _)| id);
+            let Some(id) = expired else {
+                break;
+            };
+            let branch = self.branches.remove(id);
+            self.saved = self.saved.filter(|saved| saved.root != id);
+            self.record.evict(Evicted::Branch(branch));
+        }
     }
 
-    fn nil_replace(&mut self, id: usize) -> Option<Branch<E, N>> {
-        let dest = self.branches.get_mut(id)?;
-        let dest = mem::replace(dest, Branch::NIL);
-        Some(dest)
+    /// Removes the branches without children until the edits fit in the budget,
+    /// starting with the branch that was split off first.
+    fn rm_branches_over_budget(&mut self) {
+        while self.record.is_over_budget() {
+            let first = self
+                .branches()
+                .filter(|&(id, _)| id != self.root && self.children_of(id).next().is_none())
+                .min_by_key(|&(id, _)| self.fork_of(id))
+                .map(|(id, _)| id);
+            let Some(id) = first else {
+                break;
+            };
+            let branch = self.branches.remove(id);
+            self.saved = self.saved.filter(|saved| saved.root != id);
+            self.record.evict(Evicted::Branch(branch));
+        }
     }
 }
 
-impl<E, const N: usize, S: Slot> History<E, N, S> {
+impl<E, const N: usize, S: Slot, H: Evict<E, N>, K> History<E, N, S, H, K> {
     /// Marks the target as currently being in a saved or unsaved state.
     pub fn set_saved(&mut self) {
         self.saved = None;
@@ -263,18 +473,118 @@ impl<E, const N: usize, S: Slot> History<E, N, S> {
         self.record.clear_saved();
     }
 
+    /// Sets a barrier at the current position that the history can not undo past.
+    ///
+    /// Branches that were split off before the barrier can not be reached with [`History::go_to`].
+    /// See [`Record::set_barrier`] for more information.
+    pub fn set_barrier(&mut self) {
+        self.record.set_barrier();
+    }
+
+    /// Removes the barrier.
+    pub fn clear_barrier(&mut self) {
+        self.record.clear_barrier();
+    }
+
     /// Removes all edits from the history without undoing them.
     pub fn clear(&mut self) {
         let old_root = self.root;
         self.saved = None;
         self.record.clear();
-        self.branches.clear();
+        for (id, branch) in mem::take(&mut self.branches) {
+            if id != old_root {
+                self.record.evict(Evicted::Branch(branch));
+            }
+        }
         self.root = self.branches.insert(Branch::NIL);
         self.record
             .socket
             .emit_if(old_root != self.root, || Event::Root(self.root));
     }
 
+    /// Removes all edits after the head without undoing them.
+    ///
+    /// If `branch` is `true` the edits are moved into a new branch instead of being removed,
+    /// otherwise the branches that were split off from the removed edits are removed too.
+    pub fn discard_redo(&mut self, branch: bool) {
+        let head = self.head();
+        let (tail, rm_saved) = self.record.coalesce(|record| record.rm_tail());
+        if tail.is_empty() {
+            return;
+        }
+
+        if branch {
+            let root = self.root;
+            let new = self.branches.insert(Branch {
+                parent: head,
+                entries: tail,
+            });
+            // The branches that were split off from the tail are now children of the new branch.
+            self.branches
+                .iter_mut()
+                .filter(|(id, child)| {
+                    *id != root
+                        && *id != new
+                        && child.parent.root == root
+                        && child.parent.index > head.index
+                })
+                .for_each(|(_, child)| child.parent.root = new);
+            if let Some(saved) = rm_saved {
+                self.saved = Some(At::new(new, saved));
+            }
+        } else {
+            for index in head.index + 1..=head.index + tail.len() {
+                self.rm_child_of(At::new(self.root, index));
+            }
+            self.record.evict_entries(tail);
+        }
+    }
+
+    /// Removes the `count` oldest edits from the current root branch without undoing them.
+    ///
+    /// Branches that were split off from the removed states are removed too.
+    /// See [`Record::truncate_front`] for more information.
+    pub fn truncate_front(&mut self, count: usize) -> usize {
+        let count = self.record.truncate_front(count);
+        if count > 0 {
+            self.rm_states(Removed::Front(count));
+        }
+        count
+    }
+
+    /// Removes the edits in the current root branch that were made before `time` without undoing them.
+    ///
+    /// See [`History::truncate_front`] for more information.
+    #[cfg(feature = "std")]
+    pub fn prune_before(&mut self, time: SystemTime) -> usize {
+        self.truncate_front(self.record.count_before(time))
+    }
+
+    /// Removes the edits and branches that are older than the max age without undoing them.
+    ///
+    /// Branches are only removed when all of their edits and the edits in their children are expired,
+    /// or when they were split off from a removed state.
+    /// See [`Record::enforce_retention`] for more information.
+    #[cfg(feature = "std")]
+    pub fn enforce_retention(&mut self, now: SystemTime) -> usize {
+        let Some(time) = self.record.expires_before(now) else {
+            return 0;
+        };
+        let count = self.record.count_before(time);
+        let len = self.record.len();
+        if count > self.record.head() {
+            self.discard_redo(false);
+        }
+        // The expired edits after the head were discarded with the tail.
+        let count = count.min(self.record.head());
+        if count > 0 {
+            let removed = self.record.coalesce(|record| record.rm_front(count));
+            self.rm_states(removed);
+        }
+        self.rm_branches_before(time);
+        len - self.record.len()
+    }
+
     fn set_root(&mut self, new: At, rm_saved: Option<usize>) {
         debug_assert_ne!(self.root, new.root);
 
@@ -314,21 +624,39 @@ impl<E, const N: usize, S: Slot> History<E, N, S> {
     }
 }
 
-impl<E: Edit, const N: usize, S: Slot> History<E, N, S> {
+impl<E: Edit, const N: usize, S: Slot, H: Evict<E, N>, K: Keyframe<E::Target>>
+    History<E, N, S, H, K>
+{
+    /// Returns a checkpoint guard that cancels the changes when dropped.
+    ///
+    /// See [`Guard`] for more information.
+    pub fn guard<'a, const M: usize>(
+        &'a mut self,
+        target: &'a mut E::Target,
+    ) -> Guard<'a, E, N, M, S, H, K> {
+        Guard::new(self, target)
+    }
+
     /// Pushes the [`Edit`] to the top of the history and executes its [`Edit::edit`] method.
+    ///
+    /// See [`Record::edit`] for more information.
     pub fn edit(&mut self, target: &mut E::Target, edit: E) -> E::Output {
-        let head = self.head();
-        let (output, merged, tail, rm_saved) = self.record.edit_and_push(target, Entry::new(edit));
+        let mut entry = self.record.new_entry(edit);
+        let output = entry.edit(target);
+        self.push(entry, true);
+        self.record.snapshot(target);
+        output
+    }
 
-        // Check if the limit has been reached.
-        if !merged && head.index == self.record.head() {
-            let root = self.root;
-            self.rm_child_of(At::new(root, 0));
-            self.branches
-                .iter_mut()
-                .filter(|(_, child)| child.parent.root == root)
-                .for_each(|(_, child)| child.parent.index -= 1);
-        }
+    /// Pushes the entry, which must already have been applied, on top of the history.
+    ///
+    /// See [`Record::push`] for more information.
+    pub(crate) fn push(&mut self, entry: Entry<E>, merge: bool) {
+        let head = self.head();
+        // The split off tail depends on the active edit, so it can not be merged into.
+        let merge = merge && !self.record.can_redo();
+        let (mut removed, tail, rm_saved) = self.record.push(entry, merge);
+        removed.extend(self.record.expire());
 
         // Handle new branch by putting the tail into the empty root branch
         // before we swap the root with the new branch.
@@ -342,7 +670,12 @@ impl<E: Edit, const N: usize, S: Slot> History<E, N, S> {
             self.set_root(new, rm_saved);
         }
 
-        output
+        // Remove the branches of the states that were removed by the limit, budget or max age.
+        // This also moves the new branch, since it was split off before the states were removed.
+        for removed in removed {
+            self.rm_states(removed);
+        }
+        self.rm_branches_over_budget();
     }
 
     /// Calls the [`Edit::undo`] method for the active edit
@@ -357,6 +690,55 @@ impl<E: Edit, const N: usize, S: Slot> History<E, N, S> {
         self.record.redo(target)
     }
 
+    /// Replaces the active edit without pushing a new entry on the history.
+    ///
+    /// The saved state is cleared if it depended on the amended edit,
+    /// including when it is in a branch that was created after the amended edit.
+    /// See [`Record::amend`] for more information.
+    pub fn amend(&mut self, target: &mut E::Target, f: impl FnOnce(&mut E)) -> Option<E::Output> {
+        let index = self.record.head();
+        let output = self.record.amend(target, f)?;
+        self.saved = self.saved.filter(|saved| self.fork_of(saved.root) < index);
+        // The states in the branches split off after the amended entry have changed as well.
+        let mut changed: Vec<_, N> = self
+            .children_of(self.root)
+            .filter(|&id| self.branches[id].parent.index >= index)
+            .collect();
+        while let Some(id) = changed.pop() {
+            for i in 0..self.branches[id].entries.len() {
+                let new = self.record.new_id();
+                let entry = self.branches[id].entries.get_mut(i);
+                entry.expect("in the range").set_id(new);
+            }
+            changed.extend(self.children_of(id))
+        }
+        Some(output)
+    }
+
+    /// Calls [`History::undo`] up to `n` times.
+    ///
+    /// See [`Record::undo_n`] for more information.
+    pub fn undo_n(&mut self, target: &mut E::Target, n: usize) -> Vec<E::Output, N> {
+        self.record.undo_n(target, n)
+    }
+
+    /// Calls [`History::redo`] up to `n` times.
+    ///
+    /// See [`Record::redo_n`] for more information.
+    pub fn redo_n(&mut self, target: &mut E::Target, n: usize) -> Vec<E::Output, N> {
+        self.record.redo_n(target, n)
+    }
+
+    /// Undoes all the edits in the current branch of the history.
+    pub fn undo_all(&mut self, target: &mut E::Target) -> Vec<E::Output, N> {
+        self.record.undo_all(target)
+    }
+
+    /// Redoes all the edits in the current branch of the history.
+    pub fn redo_all(&mut self, target: &mut E::Target) -> Vec<E::Output, N> {
+        self.record.redo_all(target)
+    }
+
     /// Revert the changes done to the target since the saved state.
     pub fn revert(&mut self, target: &mut E::Target) -> Vec<E::Output, N> {
         let Some(saved) = self.saved() else {
@@ -366,47 +748,264 @@ impl<E: Edit, const N: usize, S: Slot> History<E, N, S> {
     }
 
     /// Repeatedly calls [`Edit::undo`] or [`Edit::redo`] until the edit at `at` is reached.
+    ///
+    /// If the keyframes are set, the closest snapshot on the path to `at` is restored first
+    /// if that is fewer steps away. See [`Record::go_to`] for more information.
     pub fn go_to(&mut self, target: &mut E::Target, at: At) -> Vec<E::Output, N> {
+        if self.root != at.root
+            && self.branches.contains(at.root)
+            && Some(self.fork_of(at.root)) >= self.record.barrier
+        {
+            let fork = self.fork_of(at.root);
+            let steps = self.record.index.abs_diff(fork) + at.index.abs_diff(fork);
+            if let Some((index, id)) = self.closest_keyframe(at) {
+                if index.abs_diff(at.index) < steps {
+                    self.go_to_by(None, At::new(at.root, index));
+                    let keyframes = self.record.keyframes().expect("keyframes are set");
+                    let restored = keyframes.restore(id, target);
+                    debug_assert!(restored);
+                    return self.record.go_to(target, at.index);
+                }
+            }
+        }
+        self.go_to_by(Some(target), at)
+    }
+
+    /// Returns the index and id of the state closest to `at` that has a snapshot,
+    /// only looking at the states on the path from the start to the branch of `at`.
+    fn closest_keyframe(&self, at: At) -> Option<(usize, usize)> {
+        let barrier = self.record.barrier.unwrap_or(0);
+        self.record
+            .keyframes()?
+            .ids()
+            .filter_map(|id| Some((self.index_on_path(at.root, id)?, id)))
+            .filter(|&(index, _)| index >= barrier)
+            .min_by_key(|&(index, _)| index.abs_diff(at.index))
+    }
+
+    /// Returns the index of the state with the id if it is on the path from the start to the branch.
+    fn index_on_path(&self, mut branch: usize, id: usize) -> Option<usize> {
+        // The states in a branch after the branch on the path was split off are not on the path.
+        let mut end = usize::MAX;
+        while branch != self.root {
+            let Branch { parent, entries } = self.branches.get(branch)?;
+            if let Some(i) = entries.binary_search_by_key(id, Entry::id) {
+                let index = parent.index + i + 1;
+                return (index <= end).then_some(index);
+            }
+            end = parent.index;
+            branch = parent.root;
+        }
+        let index = self.record.position_of(id)? + 1;
+        (index <= end).then_some(index)
+    }
+
+    /// Moves to `at` by calling the edits on the target, or without calling them if there is no target.
+    fn go_to_by(&mut self, mut target: Option<&mut E::Target>, at: At) -> Vec<E::Output, N> {
         if self.root == at.root {
-            return self.record.go_to(target, at.index);
+            return self.record_go_to(target, at.index);
+        }
+
+        // The path to the branch can not go past the barrier.
+        if self.branches.contains(at.root) && Some(self.fork_of(at.root)) < self.record.barrier {
+            return Vec::new();
         }
 
         // Get the path from `root` to `branch`.
         let Some(path) = self.mk_path(at.root) else {
             return Vec::new();
         };
+        // The states can be moved when the limit removes states on the way,
+        // so `at` is moved as much as the state its branch was split off from.
+        let fork = self.branches[at.root].parent.index;
 
+        let mut index = None;
         let mut outputs = Vec::new();
-        for (id, branch) in path {
+        for id in path {
+            // The branch is removed if the state it was split off from was removed on the way.
+            let Some(branch) = self.branches.get_mut(id) else {
+                return outputs;
+            };
+            let parent = branch.parent;
+            let entries = mem::take(&mut branch.entries);
+            // The edits are counted again by the budget when they are pushed.
+            self.record.size -= entries
+                .iter()
+                .map(|entry| self.record.size_of(entry))
+                .sum::<usize>();
+            if id == at.root {
+                index = at.index.checked_sub(fork - parent.index);
+            }
             // Move to the parent of the branch so we can apply the edits in the branch on top of it.
-            let outs = self.record.go_to(target, branch.parent.index);
+            let outs = self.record_go_to(target.as_deref_mut(), parent.index);
             for en in outs {
                 let _ = outputs.push(en);
             }
+            // If there are no edits after the parent of the branch, the edits are
+            // pushed on top of the root and the branch is merged into the root.
+            let mut saved = None;
+            if entries.is_empty() || !self.record.can_redo() {
+                saved = self.merge_into_root(id);
+            }
             // Apply the edits in the branch and move older edits into their own branch.
-            for entry in branch.entries {
-                let index = self.record.head();
-                let (_, _, entries, rm_saved) = self.record.redo_and_push(target, entry);
-                if !entries.is_empty() {
-                    let new = At::new(id, index);
+            for entry in entries {
+                let head = self.record.head();
+                let (removed, tail, rm_saved) = match target.as_deref_mut() {
+                    Some(target) => {
+                        let (_, removed, tail, rm_saved) = self.record.redo_and_push(target, entry);
+                        (removed, tail, rm_saved)
+                    }
+                    None => self.record.push(entry, false),
+                };
+                if !tail.is_empty() {
+                    let new = At::new(id, head);
                     let root = self.branches.get_mut(self.root).unwrap();
                     debug_assert!(root.entries.is_empty());
                     root.parent = new;
-                    root.entries = entries;
+                    root.entries = tail;
                     self.set_root(new, rm_saved);
                 }
+                // Remove the branches of the states that were removed by the limit or budget.
+                for removed in removed {
+                    index = index.and_then(|index| removed.shift(index));
+                    saved = saved.and_then(|saved| removed.shift(saved));
+                    self.rm_states(removed);
+                }
+            }
+            if saved.is_some() {
+                self.record.saved = saved;
             }
         }
 
-        let outs = self.record.go_to(target, at.index);
+        // The state is removed if the limit was reached on the way.
+        let Some(index) = index else {
+            return outputs;
+        };
+        let outs = self.record_go_to(target, index);
         for en in outs {
             let _ = outputs.push(en);
         }
         outputs
     }
+
+    /// Applies the edits on the path to `at` to a target in the state before the oldest entry.
+    ///
+    /// The history is not changed. Does nothing if `at` does not exist.
+    /// The target must be in the same state as for [`Record::replay`].
+    pub fn replay_to(&self, at: At, target: &mut E::Target) -> Vec<E::Output, N>
+    where
+        E: Clone,
+    {
+        // The branches on the path from `at` to the root branch,
+        // together with the last state in each branch that is on the path.
+        let mut path = Vec::<_, N>::new();
+        let mut id = at.root;
+        let mut end = at.index;
+        while id != self.root {
+            let Some(branch) = self.branches.get(id) else {
+                return Vec::new();
+            };
+            if path.is_empty() && end > branch.parent.index + branch.entries.len() {
+                return Vec::new();
+            }
+            let _ = path.push((id, end));
+            end = branch.parent.index;
+            id = branch.parent.root;
+        }
+        if end > self.record.len() {
+            return Vec::new();
+        }
+
+        let mut outputs = self.record.replay_until(target, end);
+        for &(id, end) in path.iter().rev() {
+            let branch = &self.branches[id];
+            let count = end.saturating_sub(branch.parent.index);
+            for entry in branch.entries.iter().take(count) {
+                let _ = outputs.push(entry.as_ref().clone().redo(target));
+            }
+        }
+        outputs
+    }
+
+    /// Removes the branch and moves its children into the root branch.
+    ///
+    /// Returns the saved state if it was in the branch,
+    /// which must be set on the record after the edits in the branch are pushed.
+    fn merge_into_root(&mut self, id: usize) -> Option<usize> {
+        self.branches.remove(id);
+        let root = self.root;
+        self.branches
+            .iter_mut()
+            .filter(|(_, child)| child.parent.root == id)
+            .for_each(|(_, child)| child.parent.root = root);
+        let saved = self.saved.filter(|saved| saved.root == id)?;
+        self.saved = None;
+        Some(saved.index)
+    }
+
+    fn record_go_to(&mut self, target: Option<&mut E::Target>, index: usize) -> Vec<E::Output, N> {
+        match target {
+            Some(target) => self.record.go_to(target, index),
+            None => {
+                self.record.move_to(index);
+                Vec::new()
+            }
+        }
+    }
 }
 
-impl<E: fmt::Display, const N: usize, S> History<E, N, S> {
+impl<E: Edit, const N: usize, S: Slot, H: Evict<E, N>, K: Keyframe<E::Target>>
+    History<E, N, S, H, K>
+{
+    /// Squashes the entries in the range of the current root branch into a single entry.
+    ///
+    /// The range is split at the states that other branches were split off from,
+    /// and each part is squashed on its own, so the branches are kept and moved
+    /// to the end of the squashed entry they were split off after.
+    /// See [`Record::squash_with`] for more information.
+    pub fn squash_with(
+        &mut self,
+        range: impl RangeBounds<usize>,
+        mut combine: impl FnMut(Vec<E, N>) -> E,
+    ) -> bool {
+        let Some((start, mut end)) = self.record.squash_bounds(range) else {
+            return false;
+        };
+        let mut forks: Vec<_, N> = self
+            .children_of(self.root)
+            .map(|id| self.branches[id].parent.index)
+            .filter(|&index| start < index && index < end)
+            .collect();
+        forks.sort_unstable();
+        // The parts are squashed from the end so the start of each part is not moved.
+        while start < end {
+            let fork = forks.pop().unwrap_or(start);
+            let len = self.record.squash_range(fork, end, &mut combine);
+            self.shift_after(end - 1, end - fork - len);
+            forks.retain(|&index| index < fork);
+            end = fork;
+        }
+        true
+    }
+}
+
+impl<
+        E: Edit + FromIterator<E>,
+        const N: usize,
+        S: Slot,
+        H: Evict<E, N>,
+        K: Keyframe<E::Target>,
+    > History<E, N, S, H, K>
+{
+    /// Squashes the entries in the range of the current root branch into a single entry.
+    ///
+    /// See [`History::squash_with`] for more information.
+    pub fn squash(&mut self, range: impl RangeBounds<usize>) -> bool {
+        self.squash_with(range, |edits| edits.into_iter().collect())
+    }
+}
+
+impl<E: fmt::Display, const N: usize, S, H, K> History<E, N, S, H, K> {
     /// Returns the string of the edit which will be undone
     /// in the next call to [`History::undo`].
     pub fn undo_string<const SIZE: usize>(&self) -> Option<String<SIZE>> {
@@ -420,14 +1019,182 @@ impl<E: fmt::Display, const N: usize, S> History<E, N, S> {
     }
 }
 
+/// Serializes the history with the branches numbered from zero in the order they are stored,
+/// so the ids that are read back can be checked against the number of branches.
+#[cfg(feature = "serde")]
+impl<E: Serialize, const N: usize, S, H, K> Serialize for History<E, N, S, H, K> {
+    fn serialize<Z: Serializer>(&self, serializer: Z) -> Result<Z::Ok, Z::Error> {
+        #[derive(Serialize)]
+        #[serde(bound = "E: Serialize")]
+        struct BranchRef<'a, E, const N: usize> {
+            parent: At,
+            entries: &'a Deque<Entry<E>, N>,
+        }
+
+        struct Branches<'a, E, const N: usize, S, H, K>(
+            &'a History<E, N, S, H, K>,
+            &'a Slab<usize>,
+        );
+
+        impl<E: Serialize, const N: usize, S, H, K> Serialize for Branches<'_, E, N, S, H, K> {
+            fn serialize<Z: Serializer>(&self, serializer: Z) -> Result<Z::Ok, Z::Error> {
+                let Branches(history, dense) = *self;
+                serializer.collect_map(history.branches.iter().map(|(id, branch)| {
+                    let parent = if id == history.root {
+                        branch.parent
+                    } else {
+                        dense_at(dense, branch.parent)
+                    };
+                    let entries = &branch.entries;
+                    (dense[id], BranchRef { parent, entries })
+                }))
+            }
+        }
+
+        let dense = self.dense_ids();
+        let mut state = serializer.serialize_struct("History", 4)?;
+        state.serialize_field("root", &dense[self.root])?;
+        state.serialize_field("saved", &self.saved.map(|saved| dense_at(&dense, saved)))?;
+        state.serialize_field("record", &self.record)?;
+        state.serialize_field("branches", &Branches(self, &dense))?;
+        state.end()
+    }
+}
+
+/// Reads the branches without trusting their ids, see [`collect_branches`].
+#[cfg(feature = "serde")]
+fn deserialize_branches<'de, D, E, const N: usize>(
+    deserializer: D,
+) -> Result<Slab<Branch<E, N>>, D::Error>
+where
+    D: Deserializer<'de>,
+    E: Deserialize<'de>,
+{
+    struct Visitor<E, const N: usize>(PhantomData<E>);
+
+    impl<'de, E: Deserialize<'de>, const N: usize> de::Visitor<'de> for Visitor<E, N> {
+        type Value = Slab<(usize, Branch<E, N>)>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a map of branches")
+        }
+
+        fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            let mut branches = Slab::new();
+            while let Some(branch) = map.next_entry()? {
+                branches.insert(branch);
+            }
+            Ok(branches)
+        }
+    }
+
+    let branches = deserializer.deserialize_map(Visitor(PhantomData))?;
+    collect_branches(branches).map_err(de::Error::custom)
+}
+
+/// Deserializes the history and checks that its invariants hold.
+///
+/// See [`History::validate`] for more information.
+#[cfg(feature = "serde")]
+impl<'de, E: Deserialize<'de>, const N: usize, S, H, K> Deserialize<'de>
+    for History<E, N, S, H, K>
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut history = History::deserialize(deserializer)?;
+        history.validate().map_err(serde::de::Error::custom)?;
+        history.renew_ids();
+        Ok(history)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<E, const N: usize, S, H, K> History<E, N, S, H, K> {
+    /// Deserializes the history and connects the slot to it.
+    ///
+    /// The slot is not serialized, so it needs to be connected again after loading.
+    pub fn deserialize_with_slot<'de, D: Deserializer<'de>>(
+        deserializer: D,
+        slot: S,
+    ) -> Result<Self, D::Error>
+    where
+        E: Deserialize<'de>,
+    {
+        let mut history = <Self as Deserialize>::deserialize(deserializer)?;
+        history.connect(slot);
+        Ok(history)
+    }
+}
+
+/// The branches are numbered from zero in the order they are stored when the history is encoded.
+/// Decoding fails if a branch has more than `N` entries, if the branch ids are duplicated
+/// or not below the number of branches, or if the invariants do not hold.
+/// See [`Record`] for more information.
+#[cfg(feature = "binary")]
+impl<E: Codec, const N: usize, S, H, K> Codec for History<E, N, S, H, K> {
+    fn encode(&self, buf: &mut [u8]) -> Option<usize> {
+        let dense = self.dense_ids();
+        let mut w = Writer::new(buf);
+        w.usize(dense[self.root])?;
+        match self.saved {
+            Some(saved) => {
+                w.u8(1)?;
+                w.value(&dense_at(&dense, saved))?;
+            }
+            None => w.u8(0)?,
+        }
+        w.value(&self.record)?;
+        w.usize(self.branches.len() - 1)?;
+        for (id, branch) in self.branches().filter(|&(id, _)| id != self.root) {
+            w.usize(dense[id])?;
+            w.value(&dense_at(&dense, branch.parent))?;
+            w.usize(branch.len())?;
+            for entry in branch.entries.iter() {
+                w.value(entry)?;
+            }
+        }
+        Some(w.len())
+    }
+
+    fn decode(bytes: &[u8]) -> Option<(Self, usize)> {
+        let mut r = Reader::new(bytes);
+        let root = r.usize()?;
+        let saved = match r.u8()? {
+            0 => None,
+            1 => Some(r.value()?),
+            _ => return None,
+        };
+        let record = r.value()?;
+        let mut branches = Slab::new();
+        branches.insert((root, Branch::NIL));
+        for _ in 0..r.usize()? {
+            let id = r.usize()?;
+            let parent = r.value()?;
+            let mut entries = Deque::new();
+            for _ in 0..r.usize()? {
+                entries.push_back(r.value()?).ok()?;
+            }
+            branches.insert((id, Branch { parent, entries }));
+        }
+        let mut history = History {
+            root,
+            saved,
+            record,
+            branches: collect_branches(branches).ok()?,
+        };
+        history.validate().ok()?;
+        history.renew_ids();
+        Some((history, r.len()))
+    }
+}
+
 impl<E, const N: usize> Default for History<E, N> {
     fn default() -> History<E, N> {
         History::new()
     }
 }
 
-impl<E, const N: usize, S> From<Record<E, N, S>> for History<E, N, S> {
-    fn from(record: Record<E, N, S>) -> Self {
+impl<E, const N: usize, S, H, K> From<Record<E, N, S, H, K>> for History<E, N, S, H, K> {
+    fn from(record: Record<E, N, S, H, K>) -> Self {
         let mut branches = Slab::new();
         let root = branches.insert(Branch::NIL);
         History {
@@ -439,9 +1206,17 @@ impl<E, const N: usize, S> From<Record<E, N, S>> for History<E, N, S> {
     }
 }
 
-impl<E, const N: usize, F> From<History<E, N, F>> for Record<E, N, F> {
-    fn from(history: History<E, N, F>) -> Record<E, N, F> {
-        history.record
+impl<E, const N: usize, S, H, K> From<History<E, N, S, H, K>> for Record<E, N, S, H, K> {
+    fn from(history: History<E, N, S, H, K>) -> Record<E, N, S, H, K> {
+        let mut record = history.record;
+        // The edits in the branches are dropped with the history.
+        for (_, branch) in history.branches {
+            branch
+                .entries
+                .into_iter()
+                .for_each(|entry| record.discard(entry));
+        }
+        record
     }
 }
 
@@ -476,7 +1251,7 @@ impl<E, const N: usize> Branch<E, N> {
 
     /// Returns the edit at the index.
     pub fn get_entry(&self, index: usize) -> Option<&Entry<E>> {
-        self.entries.iter().nth(index)
+        self.entries.get(index)
     }
 
     /// Returns an iterator over the edits in the branch.
@@ -484,3 +1259,69 @@ impl<E, const N: usize> Branch<E, N> {
         self.entries.iter()
     }
 }
+
+impl<E, const N: usize, S, H, K> History<E, N, S, H, K> {
+    /// Returns the ids the branches are written with, keyed by their ids in the history.
+    ///
+    /// Removed branches leave gaps in the slab, so the branches are numbered by their
+    /// position instead. This lets the ids that are read back be checked against the
+    /// number of branches in [`collect_branches`].
+    #[cfg(any(feature = "alloc", feature = "serde", feature = "binary"))]
+    pub(crate) fn dense_ids(&self) -> Slab<usize> {
+        self.branches
+            .iter()
+            .enumerate()
+            .map(|(dense, (id, _))| (id, dense))
+            .collect()
+    }
+}
+
+impl<E, const N: usize, S: Slot, H, K> History<E, N, S, H, K> {
+    /// Renumbers the branches with the ids from [`History::dense_ids`],
+    /// so the history has the same ids as when it is read back.
+    ///
+    /// Emits [`Event::Root`] if the id of the root branch is changed.
+    #[cfg(feature = "std")]
+    pub(crate) fn compact_ids(&mut self) {
+        let dense = self.dense_ids();
+        let root = self.root;
+        self.branches = mem::take(&mut self.branches)
+            .into_iter()
+            .map(|(id, mut branch)| {
+                if id != root {
+                    branch.parent = dense_at(&dense, branch.parent);
+                }
+                (dense[id], branch)
+            })
+            .collect();
+        self.root = dense[root];
+        self.saved = self.saved.map(|saved| dense_at(&dense, saved));
+        self.record
+            .socket
+            .emit_if(self.root != root, || Event::Root(self.root));
+    }
+}
+
+/// Maps the branch of the position to its id from [`History::dense_ids`].
+#[cfg(any(feature = "alloc", feature = "serde", feature = "binary"))]
+pub(crate) fn dense_at(dense: &Slab<usize>, at: At) -> At {
+    At::new(dense[at.root], at.index)
+}
+
+/// Collects the branches that were read into a slab keyed by their ids.
+///
+/// The ids must be unique and below the number of branches,
+/// so an id that is corrupted can not make the slab allocate more than needed.
+#[cfg(any(feature = "alloc", feature = "serde", feature = "binary"))]
+pub(crate) fn collect_branches<E, const N: usize>(
+    branches: Slab<(usize, Branch<E, N>)>,
+) -> Result<Slab<Branch<E, N>>, InvariantError> {
+    let mut seen: Slab<bool> = (0..branches.len()).map(|id| (id, false)).collect();
+    for (_, &(id, _)) in &branches {
+        match seen.get_mut(id) {
+            Some(seen) if !*seen => *seen = true,
+            _ => return Err(InvariantError::InvalidId(id)),
+        }
+    }
+    Ok(branches.into_iter().map(|(_, branch)| branch).collect())
+}
@@ -1,25 +1,27 @@
 use crate::record::Builder as RecordBuilder;
-use crate::History;
+use crate::{Edit, EditSize, Evict, History, Keyframe};
+#[cfg(feature = "std")]
+use std::time::Duration;
 
 /// Builder for a [`History`].
 ///
 /// # Examples
 /// ```
+/// # use heapless::String;
 /// # use undo::{Add, History};
-/// # let mut target = String::new();
-/// let mut history = History::builder()
+/// # let mut target = String::<256>::new();
+/// let mut history = History::<_, 128, _>::builder()
 ///     .limit(100)
-///     .capacity(100)
 ///     .connect(|s| { dbg!(s); })
 ///     .build();
 /// # history.edit(&mut target, Add('a'));
 /// ```
 #[derive(Debug)]
-pub struct Builder<E, const N: usize, S = ()>(RecordBuilder<E, N, S>);
+pub struct Builder<E, const N: usize, S = (), H = (), K = ()>(RecordBuilder<E, N, S, H, K>);
 
-impl<E, const N: usize, S> Builder<E, N, S> {
-    /// Sets the capacity for the history.
-    // pub fn capacity(self, capacity: usize) -> Builder<E, S> {
+impl<E, const N: usize, S, H, K> Builder<E, N, S, H, K> {
+    // Sets the capacity for the history.
+    // pub fn capacity(self, capacity: usize) -> Builder<E, S, H, K> {
     //     Builder(self.0.capacity(capacity))
     // }
 
@@ -27,28 +29,65 @@ impl<E, const N: usize, S> Builder<E, N, S> {
     ///
     /// # Panics
     /// Panics if `limit` is `0`.
-    pub fn limit(self, limit: usize) -> Builder<E, N, S> {
+    pub fn limit(self, limit: usize) -> Builder<E, N, S, H, K> {
         Builder(self.0.limit(limit))
     }
 
     /// Sets if the target is initially in a saved state.
     /// By default the target is in a saved state.
-    pub fn saved(self, saved: bool) -> Builder<E, N, S> {
+    pub fn saved(self, saved: bool) -> Builder<E, N, S, H, K> {
         Builder(self.0.saved(saved))
     }
 
     /// Connects the slot.
-    pub fn connect(self, slot: S) -> Builder<E, N, S> {
+    pub fn connect(self, slot: S) -> Builder<E, N, S, H, K> {
         Builder(self.0.connect(slot))
     }
 
+    /// Sets the total size in bytes that the edits in the history can use, including the edits in the branches.
+    ///
+    /// The oldest edits in the current root branch are removed first, together with the branches
+    /// that were split off from them. If the edits still do not fit, the branches are removed,
+    /// starting with the branch that was split off first.
+    pub fn byte_budget(self, budget: usize) -> Builder<E, N, S, H, K>
+    where
+        E: EditSize,
+    {
+        Builder(self.0.byte_budget(budget))
+    }
+
+    /// Sets how long the edits are kept before they are removed.
+    ///
+    /// See [`record::Builder::max_age`](crate::record::Builder::max_age) for more information.
+    #[cfg(feature = "std")]
+    pub fn max_age(self, max_age: Duration) -> Builder<E, N, S, H, K> {
+        Builder(self.0.max_age(max_age))
+    }
+
+    /// Sets the hook that is called with each entry and branch that is removed from the history.
+    ///
+    /// See [`record::Builder::on_evict`](crate::record::Builder::on_evict) for more information.
+    pub fn on_evict<F: Evict<E, N>>(self, f: F) -> Builder<E, N, S, F, K> {
+        Builder(self.0.on_evict(f))
+    }
+
+    /// Sets the keyframes that store snapshots of the target while edits are applied.
+    ///
+    /// See [`record::Builder::keyframes`](crate::record::Builder::keyframes) for more information.
+    pub fn keyframes<F: Keyframe<E::Target>>(self, keyframes: F) -> Builder<E, N, S, H, F>
+    where
+        E: Edit,
+    {
+        Builder(self.0.keyframes(keyframes))
+    }
+
     /// Builds the history.
-    pub fn build(self) -> History<E, N, S> {
+    pub fn build(self) -> History<E, N, S, H, K> {
         History::from(self.0.build())
     }
 }
 
-impl<E, const N: usize, S> Default for Builder<E, N, S> {
+impl<E, const N: usize, S, H, K> Default for Builder<E, N, S, H, K> {
     fn default() -> Self {
         Builder(RecordBuilder::default())
     }
@@ -1,101 +1,556 @@
-use crate::{At, Edit, History, Slot};
-use heapless::Vec;
+use super::Branch;
+use crate::record::Savepoint;
+use crate::{At, Edit, Entry, Event, Evict, Evicted, History, Keyframe, Outcome, Slot};
+use core::mem;
+use heapless::{Deque, Vec};
+use slab::Slab;
 
 #[derive(Debug)]
-enum CheckpointEntry {
+enum CheckpointEntry<E, const N: usize> {
     Edit(usize),
     Undo,
     Redo,
+    GoTo(At),
+    Saved {
+        saved: Option<At>,
+        record_saved: Option<usize>,
+    },
+    Clear {
+        root: usize,
+        saved: Option<At>,
+        branches: Slab<Branch<E, N>>,
+        index: usize,
+        record_saved: Option<usize>,
+        barrier: Option<usize>,
+        entries: Deque<Entry<E>, N>,
+    },
 }
 
 /// Wraps a [`History`] and gives it checkpoint functionality.
 #[derive(Debug)]
-pub struct Checkpoint<'a, E, const N: usize, const M: usize, S> {
-    history: &'a mut History<E, N, S>,
-    entries: Vec<CheckpointEntry, M>,
+pub struct Checkpoint<'a, E, const N: usize, const M: usize, S, H, K> {
+    history: &'a mut History<E, N, S, H, K>,
+    entries: Vec<CheckpointEntry<E, N>, M>,
+    savepoints: Vec<Savepoint, M>,
+    generation: usize,
 }
 
-impl<E, const N: usize, const M: usize, S> Checkpoint<'_, E, N, M, S> {
-    /// Reserves capacity for at least `additional` more entries in the checkpoint.
-    ///
-    /// # Panics
-    /// Panics if the new capacity exceeds `isize::MAX` bytes.
+impl<E, const N: usize, const M: usize, S, H, K> Checkpoint<'_, E, N, M, S, H, K> {
+    // Reserves capacity for at least `additional` more entries in the checkpoint.
+    //
+    // # Panics
+    // Panics if the new capacity exceeds `isize::MAX` bytes.
     // pub fn reserve(&mut self, additional: usize) {
     //     self.entries.reserve(additional);
     // }
 
+    /// Creates a savepoint at the current position in the checkpoint.
+    ///
+    /// Savepoints can be nested, and rolling back to a savepoint
+    /// only undoes the changes made after it was created.
+    pub fn savepoint(&mut self) -> Savepoint {
+        let savepoint = Savepoint::new(self.savepoints.len(), self.entries.len(), self.generation);
+        self.generation += 1;
+        if self.savepoints.push(savepoint).is_err() {
+            panic!("Entry limit exceeded!!")
+        }
+        savepoint
+    }
+
+    /// Releases the savepoint and all savepoints created after it.
+    ///
+    /// The changes made after the savepoint are kept as part of the checkpoint.
+    pub fn release(&mut self, savepoint: Savepoint) {
+        if savepoint.is_active(&self.savepoints) {
+            self.savepoints.truncate(savepoint.depth());
+        }
+    }
+}
+
+impl<E, const N: usize, const M: usize, S, H: Evict<E, N>, K> Checkpoint<'_, E, N, M, S, H, K> {
     /// Commits the changes and consumes the checkpoint.
-    pub fn commit(self) {}
+    pub fn commit(mut self) {
+        self.evict();
+    }
+
+    /// Passes the entries and branches that were discarded by the changes to the eviction hook.
+    pub(crate) fn evict(&mut self) {
+        for entry in mem::take(&mut self.entries) {
+            if let CheckpointEntry::Clear {
+                root,
+                branches,
+                entries,
+                ..
+            } = entry
+            {
+                let record = &mut self.history.record;
+                branches
+                    .into_iter()
+                    .filter(|&(id, _)| id != root)
+                    .for_each(|(_, branch)| record.evict(Evicted::Branch(branch)));
+                record.evict_entries(entries);
+            }
+        }
+    }
 }
 
-impl<E: Edit, const N: usize, const M: usize, S: Slot> Checkpoint<'_, E, N, M, S> {
+impl<E: Edit, const N: usize, const M: usize, S: Slot, H: Evict<E, N>, K: Keyframe<E::Target>>
+    Checkpoint<'_, E, N, M, S, H, K>
+{
     /// Calls the [`History::edit`] method.
+    ///
+    /// The edit is not merged with the previous edit, so it can be cancelled on its own.
     pub fn edit(&mut self, target: &mut E::Target, edit: E) -> E::Output {
-        if self
-            .entries
-            .push(CheckpointEntry::Edit(self.history.root))
-            .is_err()
-        {
-            panic!("Entry limit exceeded!!")
-        }
-        self.history.edit(target, edit)
+        let mut entry = self.history.record.new_entry(edit);
+        let output = entry.edit(target);
+        self.push_edit(entry);
+        output
     }
 
     /// Calls the [`History::undo`] method.
     pub fn undo(&mut self, target: &mut E::Target) -> Option<E::Output> {
-        if self.entries.push(CheckpointEntry::Undo).is_err() {
-            panic!("Entry limit exceeded!!")
-        }
-        self.history.undo(target)
+        let output = self.history.undo(target)?;
+        self.push(CheckpointEntry::Undo);
+        Some(output)
     }
 
     /// Calls the [`History::redo`] method.
     pub fn redo(&mut self, target: &mut E::Target) -> Option<E::Output> {
-        if self.entries.push(CheckpointEntry::Redo).is_err() {
-            panic!("Entry limit exceeded!!")
+        let output = self.history.redo(target)?;
+        self.push(CheckpointEntry::Redo);
+        Some(output)
+    }
+
+    /// Calls the [`History::go_to`] method.
+    pub fn go_to(&mut self, target: &mut E::Target, at: At) -> Vec<E::Output, N> {
+        let old = self.history.head();
+        let outputs = self.history.go_to(target, at);
+        self.push(CheckpointEntry::GoTo(old));
+        outputs
+    }
+
+    /// Calls the [`History::revert`] method.
+    pub fn revert(&mut self, target: &mut E::Target) -> Vec<E::Output, N> {
+        let old = self.history.head();
+        let outputs = self.history.revert(target);
+        self.push(CheckpointEntry::GoTo(old));
+        outputs
+    }
+
+    /// Calls the [`History::set_saved`] method.
+    pub fn set_saved(&mut self) {
+        self.push_saved();
+        self.history.set_saved();
+    }
+
+    /// Calls the [`History::clear_saved`] method.
+    pub fn clear_saved(&mut self) {
+        self.push_saved();
+        self.history.clear_saved();
+    }
+
+    /// Calls the [`History::clear`] method.
+    pub fn clear(&mut self) {
+        let root = self.history.root;
+        let saved = self.history.saved;
+        let branches = mem::take(&mut self.history.branches);
+        let (entries, index, record_saved, barrier) = self.history.record.rm_entries();
+        self.history.clear();
+        self.push(CheckpointEntry::Clear {
+            root,
+            saved,
+            branches,
+            index,
+            record_saved,
+            barrier,
+            entries,
+        });
+    }
+
+    /// Cancels the changes made after the savepoint.
+    ///
+    /// The savepoint is kept so it can be rolled back to again,
+    /// but all savepoints created after it are released.
+    /// Does nothing if the savepoint has already been released.
+    pub fn rollback_to(
+        &mut self,
+        target: &mut E::Target,
+        savepoint: Savepoint,
+    ) -> Vec<E::Output, M> {
+        if !savepoint.is_active(&self.savepoints) {
+            return Vec::new();
         }
-        self.history.redo(target)
+        self.savepoints.truncate(savepoint.depth() + 1);
+        self.undo_to(target, savepoint.len())
     }
 
     /// Cancels the changes and consumes the checkpoint.
     pub fn cancel(mut self, target: &mut E::Target) -> Vec<E::Output, M> {
-        self.entries.as_mut_slice().reverse();
-        self.entries
-            .into_iter()
-            .filter_map(|entry| match entry {
-                CheckpointEntry::Edit(root) => {
-                    let output = self.history.undo(target)?;
-                    if self.history.root == root {
-                        self.history.record.entries.pop_back();
-                    } else {
-                        // If a new root was created when we edited earlier,
-                        // we remove it and append the entries to the previous root.
-                        let branch = self.history.branches.remove(root);
-                        debug_assert_eq!(branch.parent, self.history.head());
-
-                        let new = At::new(root, self.history.record.head());
-                        let (_, rm_saved) = self.history.record.rm_tail();
-                        for en in branch.entries {
-                            let _ = self.history.record.entries.push_front(en);
-                        }
-                        self.history.set_root(new, rm_saved);
+        self.undo_to(target, 0)
+    }
+
+    fn undo_to(&mut self, target: &mut E::Target, len: usize) -> Vec<E::Output, M> {
+        let mut outputs = Vec::new();
+        while self.entries.len() > len {
+            let entry = self.entries.pop().expect("in the range");
+            self.undo_entry(target, entry, &mut outputs);
+        }
+        outputs
+    }
+
+    fn undo_entry(
+        &mut self,
+        target: &mut E::Target,
+        entry: CheckpointEntry<E, N>,
+        outputs: &mut Vec<E::Output, M>,
+    ) {
+        let output = match entry {
+            CheckpointEntry::Edit(root) => {
+                let output = self.history.undo(target);
+                if output.is_some() {
+                    self.restore(root);
+                }
+                output
+            }
+            CheckpointEntry::Undo => self.history.redo(target),
+            CheckpointEntry::Redo => self.history.undo(target),
+            CheckpointEntry::GoTo(at) => {
+                for output in self.history.go_to(target, at) {
+                    let _ = outputs.push(output);
+                }
+                None
+            }
+            CheckpointEntry::Saved {
+                saved,
+                record_saved,
+            } => {
+                self.history.saved = saved;
+                self.history
+                    .record
+                    .coalesce(|record| record.saved = record_saved);
+                None
+            }
+            CheckpointEntry::Clear {
+                root,
+                saved,
+                branches,
+                index,
+                record_saved,
+                barrier,
+                entries,
+            } => {
+                self.history.saved = saved;
+                self.history.branches = branches;
+                self.history.record.coalesce(|record| {
+                    record.entries = entries;
+                    record.index = index;
+                    record.saved = record_saved;
+                    record.barrier = barrier;
+                });
+                let old_root = mem::replace(&mut self.history.root, root);
+                self.history
+                    .record
+                    .socket
+                    .emit_if(old_root != root, || Event::Root(root));
+                None
+            }
+        };
+        if let Some(output) = output {
+            let _ = outputs.push(output);
+        }
+    }
+
+    /// Removes the edit above the head and puts back the entries it replaced.
+    fn restore(&mut self, root: usize) {
+        if self.history.root == root {
+            let entry = self
+                .history
+                .record
+                .entries
+                .pop_back()
+                .expect("in the range");
+            self.history.record.discard(entry);
+        } else {
+            // If a new root was created when we edited earlier, the entries of the previous
+            // root are moved back into the record and the new root is removed.
+            let new = self.history.root;
+            let branch = mem::replace(&mut self.history.branches[root], Branch::NIL);
+            debug_assert_eq!(branch.parent, At::new(new, self.history.record.head()));
+            self.history.branches.remove(new);
+            self.history
+                .branches
+                .iter_mut()
+                .filter(|(_, child)| child.parent.root == new)
+                .for_each(|(_, child)| child.parent.root = root);
+            let saved = self.history.saved.filter(|saved| saved.root == root);
+            if saved.is_some() {
+                self.history.saved = None;
+            }
+            self.history.record.coalesce(|record| {
+                let (tail, _) = record.rm_tail();
+                for entry in tail {
+                    record.discard(entry);
+                }
+                for entry in branch.entries {
+                    if record.entries.push_back(entry).is_err() {
+                        unreachable!("the entries were in the record before the edit");
                     }
-                    Some(output)
                 }
-                CheckpointEntry::Undo => self.history.redo(target),
-                CheckpointEntry::Redo => self.history.undo(target),
-            })
-            .collect()
+                if let Some(saved) = saved {
+                    record.saved = Some(saved.index);
+                }
+            });
+            self.history.root = root;
+            self.history.record.socket.emit(|| Event::Root(root));
+        }
+    }
+
+    /// Pushes the applied edit on top of the history.
+    fn push_edit(&mut self, entry: Entry<E>) {
+        self.push(CheckpointEntry::Edit(self.history.root));
+        self.history.push(entry, false);
+    }
+
+    fn push_saved(&mut self) {
+        self.push(CheckpointEntry::Saved {
+            saved: self.history.saved,
+            record_saved: self.history.record.saved,
+        });
+    }
+
+    fn push(&mut self, entry: CheckpointEntry<E, N>) {
+        if self.entries.push(entry).is_err() {
+            panic!("Entry limit exceeded!!")
+        }
+    }
+}
+
+impl<E, T, Er, const N: usize, const M: usize, S, H, K> Checkpoint<'_, E, N, M, S, H, K>
+where
+    E: Edit,
+    E::Output: Outcome<Ok = T, Error = Er>,
+    S: Slot,
+    H: Evict<E, N>,
+    K: Keyframe<E::Target>,
+{
+    /// Calls [`Checkpoint::edit`], but does not push the edit if it fails.
+    pub(crate) fn try_edit(&mut self, target: &mut E::Target, edit: E) -> Result<T, Er> {
+        let mut entry = self.history.record.new_entry(edit);
+        let output = entry.edit(target).into_result()?;
+        self.push_edit(entry);
+        Ok(output)
+    }
+
+    /// Calls [`Checkpoint::undo`], but moves the index back if the undo fails.
+    pub(crate) fn try_undo(&mut self, target: &mut E::Target) -> Result<Option<T>, Er> {
+        let Some(output) = self.history.undo(target) else {
+            return Ok(None);
+        };
+        match output.into_result() {
+            Ok(output) => {
+                self.push(CheckpointEntry::Undo);
+                Ok(Some(output))
+            }
+            Err(err) => {
+                // The failed undo did not change the target, so only the index is restored.
+                let index = self.history.record.head() + 1;
+                self.history.record.coalesce(|record| record.index = index);
+                Err(err)
+            }
+        }
+    }
+
+    /// Calls [`Checkpoint::redo`], but moves the index back if the redo fails.
+    pub(crate) fn try_redo(&mut self, target: &mut E::Target) -> Result<Option<T>, Er> {
+        let Some(output) = self.history.redo(target) else {
+            return Ok(None);
+        };
+        match output.into_result() {
+            Ok(output) => {
+                self.push(CheckpointEntry::Redo);
+                Ok(Some(output))
+            }
+            Err(err) => {
+                let index = self.history.record.head() - 1;
+                self.history.record.coalesce(|record| record.index = index);
+                Err(err)
+            }
+        }
+    }
+
+    /// Moves to `at` one step at a time, stopping at the first step that fails.
+    ///
+    /// If `at` is in another branch, the edits are undone until the state where the branches
+    /// split, and then the branch is switched without calling the edits. Only one entry is
+    /// pushed to the checkpoint, no matter how many steps are taken.
+    pub(crate) fn try_go_to(&mut self, target: &mut E::Target, at: At) -> Result<Vec<T, N>, Er> {
+        let mut outputs = Vec::new();
+        let fork = if at.root == self.history.root {
+            None
+        } else if self.history.branches.contains(at.root) {
+            Some(self.history.fork_of(at.root))
+        } else {
+            return Ok(outputs);
+        };
+        if Some(fork.unwrap_or(at.index)) < self.history.record.barrier {
+            return Ok(outputs);
+        }
+        self.push(CheckpointEntry::GoTo(self.history.head()));
+        if let Some(fork) = fork {
+            self.try_step_to(target, fork, &mut outputs)?;
+            self.history.go_to_by(None, At::new(at.root, fork));
+        }
+        self.try_step_to(target, at.index, &mut outputs)?;
+        Ok(outputs)
+    }
+
+    /// Calls [`Checkpoint::try_go_to`] with the saved state.
+    pub(crate) fn try_revert(&mut self, target: &mut E::Target) -> Result<Vec<T, N>, Er> {
+        match self.history.saved() {
+            Some(saved) => self.try_go_to(target, saved),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn try_step_to(
+        &mut self,
+        target: &mut E::Target,
+        index: usize,
+        outputs: &mut Vec<T, N>,
+    ) -> Result<(), Er> {
+        let record = &mut self.history.record;
+        if index > record.len() || Some(index) < record.barrier {
+            return Ok(());
+        }
+        while record.head() != index {
+            let redo = index > record.head();
+            let output = if redo {
+                record.redo(target)
+            } else {
+                record.undo(target)
+            };
+            match output.expect("in the range").into_result() {
+                Ok(output) => {
+                    let _ = outputs.push(output);
+                }
+                Err(err) => {
+                    // The failed step did not change the target, so only the index is restored.
+                    let head = record.head();
+                    let index = if redo { head - 1 } else { head + 1 };
+                    record.coalesce(|record| record.index = index);
+                    return Err(err);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A checkpoint that cancels the changes when dropped unless it is committed.
+///
+/// The guard borrows both the history and the target, which makes sure that
+/// the changes are rolled back if the code returns early, e.g. by using `?`.
+#[derive(Debug)]
+pub struct Guard<
+    'a,
+    E: Edit,
+    const N: usize,
+    const M: usize,
+    S: Slot,
+    H: Evict<E, N>,
+    K: Keyframe<E::Target>,
+> {
+    checkpoint: Checkpoint<'a, E, N, M, S, H, K>,
+    target: &'a mut E::Target,
+    committed: bool,
+}
+
+impl<
+        'a,
+        E: Edit,
+        const N: usize,
+        const M: usize,
+        S: Slot,
+        H: Evict<E, N>,
+        K: Keyframe<E::Target>,
+    > Guard<'a, E, N, M, S, H, K>
+{
+    pub(crate) fn new(history: &'a mut History<E, N, S, H, K>, target: &'a mut E::Target) -> Self {
+        Guard {
+            checkpoint: Checkpoint::from(history),
+            target,
+            committed: false,
+        }
+    }
+
+    /// Returns a reference to the target.
+    pub fn target(&self) -> &E::Target {
+        self.target
+    }
+
+    /// Calls the [`Checkpoint::edit`] method.
+    pub fn edit(&mut self, edit: E) -> E::Output {
+        self.checkpoint.edit(self.target, edit)
+    }
+
+    /// Calls the [`Checkpoint::undo`] method.
+    pub fn undo(&mut self) -> Option<E::Output> {
+        self.checkpoint.undo(self.target)
+    }
+
+    /// Calls the [`Checkpoint::redo`] method.
+    pub fn redo(&mut self) -> Option<E::Output> {
+        self.checkpoint.redo(self.target)
+    }
+
+    /// Calls the [`Checkpoint::savepoint`] method.
+    pub fn savepoint(&mut self) -> Savepoint {
+        self.checkpoint.savepoint()
+    }
+
+    /// Calls the [`Checkpoint::release`] method.
+    pub fn release(&mut self, savepoint: Savepoint) {
+        self.checkpoint.release(savepoint);
+    }
+
+    /// Calls the [`Checkpoint::rollback_to`] method.
+    pub fn rollback_to(&mut self, savepoint: Savepoint) -> Vec<E::Output, M> {
+        self.checkpoint.rollback_to(self.target, savepoint)
+    }
+
+    /// Commits the changes and consumes the guard.
+    pub fn commit(mut self) {
+        self.committed = true;
+    }
+
+    /// Cancels the changes and consumes the guard.
+    pub fn cancel(mut self) -> Vec<E::Output, M> {
+        self.committed = true;
+        self.checkpoint.undo_to(self.target, 0)
+    }
+}
+
+impl<E: Edit, const N: usize, const M: usize, S: Slot, H: Evict<E, N>, K: Keyframe<E::Target>> Drop
+    for Guard<'_, E, N, M, S, H, K>
+{
+    fn drop(&mut self) {
+        if self.committed {
+            self.checkpoint.evict();
+        } else {
+            self.checkpoint.undo_to(self.target, 0);
+        }
     }
 }
 
-impl<'a, E, const N: usize, const M: usize, S> From<&'a mut History<E, N, S>>
-    for Checkpoint<'a, E, N, M, S>
+impl<'a, E, const N: usize, const M: usize, S, H, K> From<&'a mut History<E, N, S, H, K>>
+    for Checkpoint<'a, E, N, M, S, H, K>
 {
-    fn from(history: &'a mut History<E, N, S>) -> Self {
+    fn from(history: &'a mut History<E, N, S, H, K>) -> Self {
         Checkpoint {
             history,
             entries: Vec::new(),
+            savepoints: Vec::new(),
+            generation: 0,
         }
     }
 }
@@ -1,18 +1,19 @@
 use crate::{At, Entry, Format, History};
 use core::fmt::{self, Write};
+#[cfg(feature = "std")]
 use heapless::String;
 #[cfg(feature = "std")]
 use std::time::SystemTime;
 
 /// Configurable display formatting for the [`History`].
-pub struct Display<'a, E, const N: usize, const SIZE: usize, S> {
-    history: &'a History<E, N, S>,
+pub struct Display<'a, E, const N: usize, const SIZE: usize, S, H, K> {
+    history: &'a History<E, N, S, H, K>,
     format: Format,
     #[cfg(feature = "std")]
     st_fmt: &'a dyn Fn(SystemTime, SystemTime) -> String<SIZE>,
 }
 
-impl<'a, E, const N: usize, const SIZE: usize, S> Display<'a, E, N, SIZE, S> {
+impl<'a, E, const N: usize, const SIZE: usize, S, H, K> Display<'a, E, N, SIZE, S, H, K> {
     /// Show colored output (on by default).
     ///
     /// Requires the `colored` feature to be enabled.
@@ -40,6 +41,18 @@ impl<'a, E, const N: usize, const SIZE: usize, S> Display<'a, E, N, SIZE, S> {
         self
     }
 
+    /// Show the barrier (on by default).
+    pub fn barrier(&mut self, on: bool) -> &mut Self {
+        self.format.barrier = on;
+        self
+    }
+
+    /// Show the pinned edits (on by default).
+    pub fn pinned(&mut self, on: bool) -> &mut Self {
+        self.format.pinned = on;
+        self
+    }
+
     /// Sets the format used to display [`SystemTime`]s.
     ///
     /// The first input parameter is the current system time.
@@ -54,7 +67,7 @@ impl<'a, E, const N: usize, const SIZE: usize, S> Display<'a, E, N, SIZE, S> {
     }
 }
 
-impl<E: fmt::Display, const N: usize, const SIZE: usize, S> Display<'_, E, N, SIZE, S> {
+impl<E: fmt::Display, const N: usize, const SIZE: usize, S, H, K> Display<'_, E, N, SIZE, S, H, K> {
     fn fmt_list(
         &self,
         f: &mut fmt::Formatter,
@@ -75,8 +88,14 @@ impl<E: fmt::Display, const N: usize, const SIZE: usize, S> Display<'_, E, N, SI
             }
         }
 
-        self.format
-            .labels(f, at, self.history.head(), self.history.saved())?;
+        self.format.labels(
+            f,
+            at,
+            self.history.head(),
+            self.history.saved(),
+            self.history.barrier(),
+            entry.is_some_and(Entry::is_pinned),
+        )?;
 
         if let Some(entry) = entry {
             if self.format.detailed {
@@ -141,10 +160,10 @@ impl<E: fmt::Display, const N: usize, const SIZE: usize, S> Display<'_, E, N, SI
     }
 }
 
-impl<'a, E, const N: usize, const SIZE: usize, S> From<&'a History<E, N, S>>
-    for Display<'a, E, N, SIZE, S>
+impl<'a, E, const N: usize, const SIZE: usize, S, H, K> From<&'a History<E, N, S, H, K>>
+    for Display<'a, E, N, SIZE, S, H, K>
 {
-    fn from(history: &'a History<E, N, S>) -> Self {
+    fn from(history: &'a History<E, N, S, H, K>) -> Self {
         Display {
             history,
             format: Format::default(),
@@ -154,8 +173,8 @@ impl<'a, E, const N: usize, const SIZE: usize, S> From<&'a History<E, N, S>>
     }
 }
 
-impl<E: fmt::Display, const N: usize, const SIZE: usize, S> fmt::Display
-    for Display<'_, E, N, SIZE, S>
+impl<E: fmt::Display, const N: usize, const SIZE: usize, S, H, K> fmt::Display
+    for Display<'_, E, N, SIZE, S, H, K>
 {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         #[cfg(feature = "std")]
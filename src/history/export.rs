@@ -0,0 +1,216 @@
+use super::{collect_branches, dense_at, Branch, Builder};
+use crate::{At, Entry, History, InvariantError};
+use alloc::vec::Vec;
+use core::fmt;
+use heapless::Deque;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use slab::Slab;
+
+/// The current version of the [`Export`] format.
+pub const VERSION: u32 = 1;
+
+/// A versioned representation of a history that does not depend on its internal layout.
+///
+/// Use [`History::export`] to create it and [`History::import`] to turn it back into a history.
+/// Documents from older versions can be upgraded with [`History::import_with`].
+///
+/// The edits are stored without their timestamps and pins, and the slot, limit hooks,
+/// and barrier are not part of the format. The branches are numbered from zero in the
+/// export, so their ids can differ from the ids in the history.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug)]
+pub struct Export<E, const N: usize> {
+    /// The version of the format.
+    pub version: u32,
+    /// The limit of the history.
+    pub limit: usize,
+    /// The current position in the history.
+    pub head: At,
+    /// The saved position in the history.
+    pub saved: Option<At>,
+    /// The branches of the history.
+    ///
+    /// The root branch is the branch with the same id as the head and it has no parent.
+    /// The ids must be unique and below the number of branches.
+    pub branches: Vec<ExportBranch<E, N>>,
+}
+
+/// The version at the start of an [`Export`].
+///
+/// Decoding a document as a header only reads the version, so the rest of the document
+/// can be decoded with the layout of that version before it is passed to
+/// [`History::import_with`].
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Header {
+    /// The version of the format.
+    pub version: u32,
+}
+
+/// A branch in an [`Export`].
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug)]
+pub struct ExportBranch<E, const N: usize> {
+    /// The id of the branch.
+    pub id: usize,
+    /// The position the branch was split off from, or `None` for the root branch.
+    pub parent: Option<At>,
+    /// The edits in the branch.
+    pub edits: heapless::Vec<E, N>,
+}
+
+/// The error returned when an [`Export`] can not be imported.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ImportError {
+    /// The version of the export is not supported.
+    UnsupportedVersion(u32),
+    /// The export does not describe a valid history.
+    Invalid(InvariantError),
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImportError::UnsupportedVersion(version) => {
+                write!(f, "version {version} of the format is not supported")
+            }
+            ImportError::Invalid(error) => fmt::Display::fmt(error, f),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ImportError {}
+
+impl From<InvariantError> for ImportError {
+    fn from(error: InvariantError) -> Self {
+        ImportError::Invalid(error)
+    }
+}
+
+impl<E: Clone, const N: usize, S, H, K> History<E, N, S, H, K> {
+    /// Returns the history in the versioned [`Export`] format.
+    pub fn export(&self) -> Export<E, N> {
+        let edits = |entries: &Deque<Entry<E>, N>| {
+            entries.iter().map(|entry| entry.as_ref().clone()).collect()
+        };
+        let dense = self.dense_ids();
+        let branches = self
+            .branches()
+            .map(|(id, branch)| {
+                if id == self.root {
+                    ExportBranch {
+                        id: dense[id],
+                        parent: None,
+                        edits: edits(&self.record.entries),
+                    }
+                } else {
+                    ExportBranch {
+                        id: dense[id],
+                        parent: Some(dense_at(&dense, branch.parent)),
+                        edits: edits(&branch.entries),
+                    }
+                }
+            })
+            .collect();
+        Export {
+            version: VERSION,
+            limit: self.limit(),
+            head: dense_at(&dense, self.head()),
+            saved: self.saved().map(|saved| dense_at(&dense, saved)),
+            branches,
+        }
+    }
+}
+
+impl<E, const N: usize, S, H, K> History<E, N, S, H, K> {
+    /// Creates a history from an [`Export`] of the current version.
+    ///
+    /// The history is validated after it has been created.
+    ///
+    /// # Errors
+    /// Returns an error if the version is not [`VERSION`], or if the export does not describe
+    /// a valid history.
+    pub fn import(export: Export<E, N>) -> Result<History<E, N, S, H, K>, ImportError> {
+        History::import_from(History::builder(), export)
+    }
+
+    /// Creates a history from a document that can be in the layout of an older version.
+    ///
+    /// The `version` is read from the document first, e.g. by decoding it as a [`Header`].
+    /// Then `migrate` is called with the version and the `raw` document, which it decodes
+    /// with the layout of that version and upgrades to an [`Export`] of the current version.
+    /// It returns `None` if the version is not supported.
+    ///
+    /// # Errors
+    /// Returns an error if the version is newer than [`VERSION`], if `migrate` does not return
+    /// an export of the current version, or if the export does not describe a valid history.
+    pub fn import_with<R>(
+        version: u32,
+        raw: R,
+        migrate: impl FnOnce(u32, R) -> Option<Export<E, N>>,
+    ) -> Result<History<E, N, S, H, K>, ImportError> {
+        if version > VERSION {
+            return Err(ImportError::UnsupportedVersion(version));
+        }
+        let export = migrate(version, raw).ok_or(ImportError::UnsupportedVersion(version))?;
+        History::import(export)
+    }
+
+    /// Creates a history from an [`Export`] with the settings from `builder`, except the limit.
+    pub(crate) fn import_from(
+        builder: Builder<E, N, S, H, K>,
+        export: Export<E, N>,
+    ) -> Result<History<E, N, S, H, K>, ImportError> {
+        if export.version != VERSION {
+            return Err(ImportError::UnsupportedVersion(export.version));
+        }
+        if export.limit == 0 {
+            return Err(InvariantError::ZeroLimit.into());
+        }
+
+        let mut history = builder.limit(export.limit).build();
+        history.root = export.head.root;
+        history.record.saved = None;
+        let mut branches = Slab::with_capacity(export.branches.len());
+        for branch in export.branches {
+            let mut entries = Deque::new();
+            for edit in branch.edits {
+                if entries.push_back(Entry::new(edit)).is_err() {
+                    unreachable!("the branch has room for `N` edits");
+                }
+            }
+            match branch.parent {
+                None if branch.id == history.root => {
+                    history.record.entries = entries;
+                    branches.insert((branch.id, Branch::NIL));
+                }
+                Some(parent) if branch.id != history.root => {
+                    branches.insert((branch.id, Branch { parent, entries }));
+                }
+                _ => return Err(InvariantError::InvalidRoot.into()),
+            }
+        }
+        history.branches = collect_branches(branches)?;
+        // The sizes of the edits are not part of the export, so they are counted for the budget.
+        let record = &history.record;
+        let branches = history
+            .branches
+            .iter()
+            .flat_map(|(_, branch)| branch.entries());
+        history.record.size = record
+            .entries()
+            .chain(branches)
+            .map(|entry| record.size_of(entry))
+            .sum();
+        history.record.index = export.head.index;
+        match export.saved {
+            Some(saved) if saved.root == history.root => history.record.saved = Some(saved.index),
+            saved => history.saved = saved,
+        }
+        history.validate()?;
+        history.renew_ids();
+        Ok(history)
+    }
+}
@@ -1,4 +1,6 @@
-use crate::{Edit, History, Slot};
+use crate::history::Checkpoint;
+use crate::record::QueueOutput;
+use crate::{At, Edit, Evict, History, Keyframe, Merged, Outcome, Slot};
 use heapless::Vec;
 
 #[derive(Debug)]
@@ -6,16 +8,22 @@ enum QueueEntry<E> {
     Edit(E),
     Undo,
     Redo,
+    GoTo(At),
+    Revert,
+    SetSaved,
+    ClearSaved,
+    Clear,
 }
 
 /// Wraps a [`History`] and gives it batch queue functionality.
 ///
 /// # Examples
 /// ```
+/// # use heapless::String;
 /// # use undo::{Add, History};
-/// let mut string = String::new();
-/// let mut history = History::new();
-/// let mut queue = history.queue();
+/// let mut string = String::<256>::new();
+/// let mut history = History::<_, 32>::new();
+/// let mut queue = history.queue::<16>();
 ///
 /// queue.edit(Add('a'));
 /// queue.edit(Add('b'));
@@ -26,66 +34,181 @@ enum QueueEntry<E> {
 /// assert_eq!(string, "abc");
 /// ```
 #[derive(Debug)]
-pub struct Queue<'a, E, const N: usize, const M: usize, S> {
-    history: &'a mut History<E, N, S>,
+pub struct Queue<'a, E, const N: usize, const M: usize, S, H, K> {
+    history: &'a mut History<E, N, S, H, K>,
     entries: Vec<QueueEntry<E>, M>,
+    premerge: bool,
 }
 
-impl<E, const N: usize, const M: usize, S> Queue<'_, E, N, M, S> {
-    /// Reserves capacity for at least `additional` more entries in the queue.
-    ///
-    /// # Panics
-    /// Panics if the new capacity exceeds `isize::MAX` bytes.
+impl<E, const N: usize, const M: usize, S, H, K> Queue<'_, E, N, M, S, H, K> {
+    // Reserves capacity for at least `additional` more entries in the queue.
+    //
+    // # Panics
+    // Panics if the new capacity exceeds `isize::MAX` bytes.
     // pub fn reserve(&mut self, additional: usize) {
     //     self.entries.reserve(additional);
     // }
 
-    /// Queues a [`History::edit`] call.
-    pub fn edit(&mut self, edit: E) {
-        if self.entries.push(QueueEntry::Edit(edit)).is_err() {
-            panic!("Entry limit exceeded!!")
-        }
+    /// Merge the queued edits using [`Edit::merge`] before they are applied (off by default).
+    ///
+    /// When on, an edit that merges with the previously queued edit does not
+    /// use a new slot in the queue, and the merged edits are applied as a single edit.
+    pub fn premerge(&mut self, on: bool) -> &mut Self {
+        self.premerge = on;
+        self
     }
 
     /// Queues a [`History::undo`] call.
     pub fn undo(&mut self) {
-        if self.entries.push(QueueEntry::Undo).is_err() {
-            panic!("Entry limit exceeded!!")
-        }
+        self.push(QueueEntry::Undo);
     }
 
     /// Queues a [`History::redo`] call.
     pub fn redo(&mut self) {
-        if self.entries.push(QueueEntry::Redo).is_err() {
-            panic!("Entry limit exceeded!!")
-        }
+        self.push(QueueEntry::Redo);
+    }
+
+    /// Queues a [`History::go_to`] call.
+    pub fn go_to(&mut self, at: At) {
+        self.push(QueueEntry::GoTo(at));
+    }
+
+    /// Queues a [`History::revert`] call.
+    pub fn revert(&mut self) {
+        self.push(QueueEntry::Revert);
+    }
+
+    /// Queues a [`History::set_saved`] call.
+    pub fn set_saved(&mut self) {
+        self.push(QueueEntry::SetSaved);
+    }
+
+    /// Queues a [`History::clear_saved`] call.
+    pub fn clear_saved(&mut self) {
+        self.push(QueueEntry::ClearSaved);
+    }
+
+    /// Queues a [`History::clear`] call.
+    pub fn clear(&mut self) {
+        self.push(QueueEntry::Clear);
     }
 
     /// Cancels the queued edits.
     pub fn cancel(self) {}
+
+    fn push(&mut self, entry: QueueEntry<E>) {
+        if self.entries.push(entry).is_err() {
+            panic!("Entry limit exceeded!!")
+        }
+    }
+}
+
+impl<E: Edit, const N: usize, const M: usize, S, H, K> Queue<'_, E, N, M, S, H, K> {
+    /// Queues a [`History::edit`] call.
+    pub fn edit(&mut self, edit: E) {
+        let edit = match self.entries.last_mut() {
+            Some(QueueEntry::Edit(last)) if self.premerge => match last.merge(edit) {
+                Merged::Yes => return,
+                Merged::Annul => {
+                    self.entries.pop();
+                    return;
+                }
+                Merged::No(edit) => edit,
+            },
+            _ => edit,
+        };
+        self.push(QueueEntry::Edit(edit));
+    }
 }
 
-impl<E: Edit, const N: usize, const M: usize, S: Slot> Queue<'_, E, N, M, S> {
-    /// Applies the queued edits.
-    pub fn commit(self, target: &mut E::Target) -> Vec<E::Output, M> {
+impl<E: Edit, const N: usize, const M: usize, S: Slot, H: Evict<E, N>, K: Keyframe<E::Target>>
+    Queue<'_, E, N, M, S, H, K>
+{
+    /// Applies the queued operations in the order they were queued.
+    pub fn commit(self, target: &mut E::Target) -> Vec<QueueOutput<E::Output, N>, M> {
         self.entries
             .into_iter()
-            .filter_map(|entry| match entry {
-                QueueEntry::Edit(edit) => Some(self.history.edit(target, edit)),
-                QueueEntry::Undo => self.history.undo(target),
-                QueueEntry::Redo => self.history.redo(target),
+            .map(|entry| match entry {
+                QueueEntry::Edit(edit) => QueueOutput::Edit(self.history.edit(target, edit)),
+                QueueEntry::Undo => QueueOutput::Undo(self.history.undo(target)),
+                QueueEntry::Redo => QueueOutput::Redo(self.history.redo(target)),
+                QueueEntry::GoTo(at) => QueueOutput::GoTo(self.history.go_to(target, at)),
+                QueueEntry::Revert => QueueOutput::Revert(self.history.revert(target)),
+                QueueEntry::SetSaved => {
+                    self.history.set_saved();
+                    QueueOutput::SetSaved
+                }
+                QueueEntry::ClearSaved => {
+                    self.history.clear_saved();
+                    QueueOutput::ClearSaved
+                }
+                QueueEntry::Clear => {
+                    self.history.clear();
+                    QueueOutput::Clear
+                }
             })
             .collect()
     }
 }
 
-impl<'a, E, const N: usize, const M: usize, S> From<&'a mut History<E, N, S>>
-    for Queue<'a, E, N, M, S>
+impl<E, T, Er, const N: usize, const M: usize, S, H, K> Queue<'_, E, N, M, S, H, K>
+where
+    E: Edit,
+    E::Output: Outcome<Ok = T, Error = Er>,
+    S: Slot,
+    H: Evict<E, N>,
+    K: Keyframe<E::Target>,
+{
+    /// Applies the queued operations in the order they were queued,
+    /// or none of them if any of the operations fails.
+    ///
+    /// See [`record::Queue::commit`](crate::record::Queue::try_commit) for more information.
+    pub fn try_commit(self, target: &mut E::Target) -> Result<Vec<QueueOutput<T, N>, M>, Er> {
+        let mut checkpoint = Checkpoint::<E, N, M, S, H, K>::from(self.history);
+        let mut outputs = Vec::new();
+        for entry in self.entries {
+            let output = match entry {
+                QueueEntry::Edit(edit) => checkpoint.try_edit(target, edit).map(QueueOutput::Edit),
+                QueueEntry::Undo => checkpoint.try_undo(target).map(QueueOutput::Undo),
+                QueueEntry::Redo => checkpoint.try_redo(target).map(QueueOutput::Redo),
+                QueueEntry::GoTo(at) => checkpoint.try_go_to(target, at).map(QueueOutput::GoTo),
+                QueueEntry::Revert => checkpoint.try_revert(target).map(QueueOutput::Revert),
+                QueueEntry::SetSaved => {
+                    checkpoint.set_saved();
+                    Ok(QueueOutput::SetSaved)
+                }
+                QueueEntry::ClearSaved => {
+                    checkpoint.clear_saved();
+                    Ok(QueueOutput::ClearSaved)
+                }
+                QueueEntry::Clear => {
+                    checkpoint.clear();
+                    Ok(QueueOutput::Clear)
+                }
+            };
+            match output {
+                Ok(output) => {
+                    let _ = outputs.push(output);
+                }
+                Err(err) => {
+                    checkpoint.cancel(target);
+                    return Err(err);
+                }
+            }
+        }
+        checkpoint.commit();
+        Ok(outputs)
+    }
+}
+
+impl<'a, E, const N: usize, const M: usize, S, H, K> From<&'a mut History<E, N, S, H, K>>
+    for Queue<'a, E, N, M, S, H, K>
 {
-    fn from(history: &'a mut History<E, N, S>) -> Self {
+    fn from(history: &'a mut History<E, N, S, H, K>) -> Self {
         Queue {
             history,
             entries: Vec::new(),
+            premerge: false,
         }
     }
 }
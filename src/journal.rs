@@ -0,0 +1,393 @@
+//! An append-only journal of the changes to a history.
+
+use crate::codec::{Reader, Writer};
+use crate::history::{Builder, Export, ExportBranch};
+use crate::{At, Codec, Edit, Evict, History, Keyframe, Slot};
+use std::io::{self, Write};
+
+const EDIT: u8 = 0;
+const UNDO: u8 = 1;
+const REDO: u8 = 2;
+const GO_TO: u8 = 3;
+const SET_SAVED: u8 = 4;
+const SNAPSHOT: u8 = 5;
+
+/// The size of the length and checksum before each record in the journal.
+const HEADER: usize = 8;
+
+/// Wraps a [`History`] and writes every change to a journal.
+///
+/// The journal starts with a snapshot of the history and the target, followed by one
+/// record for each edit, undo, redo, go to, and set saved. Each record is written and
+/// flushed before the change is applied, so the history and the target can be
+/// reconstructed with [`recover`] after a crash. Use [`Journal::compact`] to start a new
+/// journal from a snapshot.
+///
+/// Only the limit is part of the snapshot, so the history is recovered with the other
+/// settings from a builder. The byte budget must be the same as in the journaled history,
+/// since it decides which edits are removed when the records are replayed. The max age,
+/// barrier, and pinned entries are not part of the journal and must not be used, since
+/// the history that is recovered would remove other edits. The slot, eviction hook, and
+/// keyframes can be set freely.
+///
+/// If a write fails, the journal may end with a partial record, and everything written
+/// after it would be lost on recovery. The journal is poisoned instead, and every change
+/// returns an error until the journal is compacted into a new writer.
+/// The journal writes to [`std::io::Write`], so it is only available with the `std` feature.
+///
+/// There is no journal for a [`Record`](crate::Record), since a history records everything a
+/// record does. A record is journaled by converting it with [`History::from`], and the history
+/// that is recovered is converted back with `Record::from`, which drops the branches.
+///
+/// # Examples
+/// ```
+/// # use heapless::String;
+/// # use undo::{journal, Add, History};
+/// # use undo::journal::Journal;
+/// let mut target = String::<256>::new();
+/// let mut journal = Journal::new(History::<_, 32>::new(), &target, Vec::new()).unwrap();
+/// journal.edit(&mut target, Add('a')).unwrap();
+/// journal.edit(&mut target, Add('b')).unwrap();
+/// journal.undo(&mut target).unwrap();
+///
+/// let (history, bytes) = journal.into_inner();
+/// let mut recovered = String::new();
+/// let (recovered_history, _) =
+///     journal::recover(&bytes, History::<Add<256>, 32>::builder(), &mut recovered).unwrap();
+/// assert_eq!(recovered, "a");
+/// assert_eq!(recovered_history.head(), history.head());
+/// ```
+#[derive(Debug)]
+pub struct Journal<E, W, const N: usize, S = (), H = (), K = ()> {
+    history: History<E, N, S, H, K>,
+    writer: W,
+    poisoned: bool,
+}
+
+impl<E: Clone, W: Write, const N: usize, S: Slot, H, K> Journal<E, W, N, S, H, K>
+where
+    E: Edit + Codec,
+    E::Target: Codec,
+{
+    /// Returns a new journal that writes a snapshot of the history and the target to `writer`.
+    ///
+    /// The branches of the history are renumbered from zero when a snapshot is written,
+    /// which is how they are numbered when the journal is recovered. An [`Event::Root`](crate::Event::Root)
+    /// is emitted if the id of the root branch is changed.
+    pub fn new(
+        mut history: History<E, N, S, H, K>,
+        target: &E::Target,
+        mut writer: W,
+    ) -> io::Result<Journal<E, W, N, S, H, K>> {
+        write_snapshot(&mut writer, &mut history, target)?;
+        Ok(Journal {
+            history,
+            writer,
+            poisoned: false,
+        })
+    }
+
+    /// Writes a snapshot of the history and the target to `writer` and continues the journal there.
+    ///
+    /// Returns the previous writer, which is no longer needed for recovery.
+    /// A poisoned journal can be written to again.
+    ///
+    /// The branches are renumbered from zero like in [`Journal::new`], so the positions that
+    /// were returned before the compaction may refer to other branches afterwards.
+    /// An [`Event::Root`](crate::Event::Root) is emitted if the id of the root branch is changed.
+    pub fn compact(&mut self, target: &E::Target, mut writer: W) -> io::Result<W> {
+        write_snapshot(&mut writer, &mut self.history, target)?;
+        self.poisoned = false;
+        Ok(core::mem::replace(&mut self.writer, writer))
+    }
+}
+
+impl<E, W, const N: usize, S, H, K> Journal<E, W, N, S, H, K> {
+    /// Returns a reference to the history.
+    pub fn history(&self) -> &History<E, N, S, H, K> {
+        &self.history
+    }
+
+    /// Returns a reference to the writer.
+    pub fn writer(&self) -> &W {
+        &self.writer
+    }
+
+    /// Returns `true` if a write has failed since the last snapshot.
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned
+    }
+
+    /// Returns the history and the writer.
+    pub fn into_inner(self) -> (History<E, N, S, H, K>, W) {
+        (self.history, self.writer)
+    }
+}
+
+impl<
+        E: Edit + Codec,
+        W: Write,
+        const N: usize,
+        S: Slot,
+        H: Evict<E, N>,
+        K: Keyframe<E::Target>,
+    > Journal<E, W, N, S, H, K>
+{
+    /// Writes the edit to the journal and then calls [`History::edit`].
+    pub fn edit(&mut self, target: &mut E::Target, edit: E) -> io::Result<E::Output> {
+        self.write(|w| {
+            w.u8(EDIT)?;
+            w.value(&edit)
+        })?;
+        Ok(self.history.edit(target, edit))
+    }
+
+    /// Writes the undo to the journal and then calls [`History::undo`].
+    pub fn undo(&mut self, target: &mut E::Target) -> io::Result<Option<E::Output>> {
+        self.write(|w| w.u8(UNDO))?;
+        Ok(self.history.undo(target))
+    }
+
+    /// Writes the redo to the journal and then calls [`History::redo`].
+    pub fn redo(&mut self, target: &mut E::Target) -> io::Result<Option<E::Output>> {
+        self.write(|w| w.u8(REDO))?;
+        Ok(self.history.redo(target))
+    }
+
+    /// Writes the jump to the journal and then calls [`History::go_to`].
+    pub fn go_to(
+        &mut self,
+        target: &mut E::Target,
+        at: At,
+    ) -> io::Result<heapless::Vec<E::Output, N>> {
+        self.write(|w| {
+            w.u8(GO_TO)?;
+            write_at(w, at)
+        })?;
+        Ok(self.history.go_to(target, at))
+    }
+
+    /// Writes the saved state to the journal and then calls [`History::set_saved`].
+    pub fn set_saved(&mut self) -> io::Result<()> {
+        self.write(|w| w.u8(SET_SAVED))?;
+        self.history.set_saved();
+        Ok(())
+    }
+
+    /// Writes a record, and poisons the journal if the write fails.
+    fn write(&mut self, f: impl Fn(&mut Writer) -> Option<()>) -> io::Result<()> {
+        if self.poisoned {
+            return Err(io::Error::other(
+                "the journal is poisoned by a failed write and must be compacted",
+            ));
+        }
+        let result = write_record(&mut self.writer, f);
+        self.poisoned = result.is_err();
+        result
+    }
+}
+
+/// Recovers the history and the target from the bytes of a journal.
+///
+/// The target is overwritten with the target from the snapshot at the start of the journal,
+/// and the history is built with the settings from `builder`, except the limit which is read
+/// from the snapshot. See [`Journal`] for the settings that must match the journaled history.
+///
+/// Reading stops at the first record that is incomplete or has an invalid checksum,
+/// so a record that was torn by a crash is ignored. Returns the history and the number of
+/// bytes that were used, which the journal should be truncated to before it is appended to again.
+///
+/// # Errors
+/// Returns an error with [`io::ErrorKind::InvalidData`] if the journal does not start with
+/// a snapshot or if a complete record can not be decoded.
+pub fn recover<E, const N: usize, S: Slot, H: Evict<E, N>, K: Keyframe<E::Target>>(
+    bytes: &[u8],
+    builder: Builder<E, N, S, H, K>,
+    target: &mut E::Target,
+) -> io::Result<(History<E, N, S, H, K>, usize)>
+where
+    E: Edit + Codec,
+    E::Target: Codec,
+{
+    let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+    let mut records = Records { bytes, pos: 0 };
+
+    let (export, snapshot) = match records.next() {
+        Some(record) => read_snapshot(record).ok_or_else(|| invalid("invalid snapshot"))?,
+        None => return Err(invalid("the journal does not start with a snapshot")),
+    };
+    let mut history = History::import_from(builder, export)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    *target = snapshot;
+
+    for record in records.by_ref() {
+        let mut r = Reader::new(record);
+        match r.u8() {
+            Some(EDIT) => {
+                let edit = r.value().ok_or_else(|| invalid("invalid edit"))?;
+                history.edit(target, edit);
+            }
+            Some(UNDO) => {
+                history.undo(target);
+            }
+            Some(REDO) => {
+                history.redo(target);
+            }
+            Some(GO_TO) => {
+                let at = read_at(&mut r).ok_or_else(|| invalid("invalid position"))?;
+                history.go_to(target, at);
+            }
+            Some(SET_SAVED) => history.set_saved(),
+            _ => return Err(invalid("invalid record")),
+        }
+    }
+    Ok((history, records.pos))
+}
+
+/// Iterates over the complete records in a journal.
+struct Records<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        let header = self.bytes.get(self.pos..self.pos + HEADER)?;
+        let len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+        let sum = u32::from_le_bytes(header[4..].try_into().unwrap());
+        let start = self.pos + HEADER;
+        let record = self.bytes.get(start..start.checked_add(len)?)?;
+        if checksum(record) != sum {
+            return None;
+        }
+        self.pos = start + len;
+        Some(record)
+    }
+}
+
+/// Writes and flushes a record with the length and checksum in front of it.
+fn write_record(writer: &mut impl Write, f: impl Fn(&mut Writer) -> Option<()>) -> io::Result<()> {
+    let mut buf = std::vec![0; 64];
+    let len = loop {
+        let mut w = Writer::new(&mut buf[HEADER..]);
+        if f(&mut w).is_some() {
+            break w.len();
+        }
+        if buf.len() > u32::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the record is too large",
+            ));
+        }
+        buf.resize(buf.len() * 2, 0);
+    };
+    let sum = checksum(&buf[HEADER..HEADER + len]);
+    buf[..4].copy_from_slice(&(len as u32).to_le_bytes());
+    buf[4..HEADER].copy_from_slice(&sum.to_le_bytes());
+    writer.write_all(&buf[..HEADER + len])?;
+    writer.flush()
+}
+
+fn write_snapshot<E: Clone + Edit + Codec, const N: usize, S: Slot, H, K>(
+    writer: &mut impl Write,
+    history: &mut History<E, N, S, H, K>,
+    target: &E::Target,
+) -> io::Result<()>
+where
+    E::Target: Codec,
+{
+    history.compact_ids();
+    let export = history.export();
+    write_record(writer, |w| {
+        w.u8(SNAPSHOT)?;
+        w.varint(export.version.into())?;
+        w.usize(export.limit)?;
+        write_at(w, export.head)?;
+        write_option(w, export.saved)?;
+        w.usize(export.branches.len())?;
+        for branch in &export.branches {
+            w.usize(branch.id)?;
+            write_option(w, branch.parent)?;
+            w.usize(branch.edits.len())?;
+            for edit in &branch.edits {
+                w.value(edit)?;
+            }
+        }
+        w.value(target)
+    })
+}
+
+/// Returns the export and the target in the snapshot, or `None` if it can not be decoded.
+fn read_snapshot<E: Edit + Codec, const N: usize>(
+    record: &[u8],
+) -> Option<(Export<E, N>, E::Target)>
+where
+    E::Target: Codec,
+{
+    let mut r = Reader::new(record);
+    if r.u8()? != SNAPSHOT {
+        return None;
+    }
+    let version = r.varint()?.try_into().ok()?;
+    let limit = r.usize()?;
+    let head = read_at(&mut r)?;
+    let saved = read_option(&mut r)?;
+    let mut branches = Vec::new();
+    for _ in 0..r.usize()? {
+        let id = r.usize()?;
+        let parent = read_option(&mut r)?;
+        let mut edits = heapless::Vec::new();
+        for _ in 0..r.usize()? {
+            edits.push(r.value()?).ok()?;
+        }
+        branches.push(ExportBranch { id, parent, edits });
+    }
+    let target = r.value()?;
+    if !r.is_empty() {
+        return None;
+    }
+    let export = Export {
+        version,
+        limit,
+        head,
+        saved,
+        branches,
+    };
+    Some((export, target))
+}
+
+fn write_at(w: &mut Writer, at: At) -> Option<()> {
+    w.usize(at.root)?;
+    w.usize(at.index)
+}
+
+fn read_at(r: &mut Reader) -> Option<At> {
+    Some(At::new(r.usize()?, r.usize()?))
+}
+
+fn write_option(w: &mut Writer, at: Option<At>) -> Option<()> {
+    match at {
+        Some(at) => {
+            w.u8(1)?;
+            write_at(w, at)
+        }
+        None => w.u8(0),
+    }
+}
+
+fn read_option(r: &mut Reader) -> Option<Option<At>> {
+    match r.u8()? {
+        0 => Some(None),
+        1 => Some(Some(read_at(r)?)),
+        _ => None,
+    }
+}
+
+/// The 32-bit FNV-1a hash of the bytes.
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash, &byte| {
+        (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+    })
+}
@@ -0,0 +1,144 @@
+use core::num::NonZeroUsize;
+use heapless::Deque;
+
+/// Stores snapshots of the target so long jumps do not have to replay every edit.
+///
+/// The snapshots are owned by the record or history they are set on with
+/// [`record::Builder::keyframes`](crate::record::Builder::keyframes), and are identified
+/// by the id the record gives each state. This is implemented by [`Keyframes`],
+/// and by `()` which does not store any snapshots.
+pub trait Keyframe<T> {
+    /// Returns `true` if a snapshot should be stored for the state at the index.
+    fn is_due(&self, index: usize) -> bool;
+
+    /// Stores a snapshot of the target for the state with the id.
+    fn insert(&mut self, id: usize, target: &T);
+
+    /// Overwrites the target with the snapshot of the state with the id.
+    ///
+    /// Returns `false` if there is no snapshot of the state.
+    fn restore(&self, id: usize, target: &mut T) -> bool;
+
+    /// Returns the ids of the states that have a snapshot.
+    fn ids(&self) -> impl Iterator<Item = usize> + '_;
+}
+
+impl<T> Keyframe<T> for () {
+    fn is_due(&self, _: usize) -> bool {
+        false
+    }
+
+    fn insert(&mut self, _: usize, _: &T) {}
+
+    fn restore(&self, _: usize, _: &mut T) -> bool {
+        false
+    }
+
+    fn ids(&self) -> impl Iterator<Item = usize> + '_ {
+        core::iter::empty()
+    }
+}
+
+/// Snapshots of the target used to make long jumps cheap.
+///
+/// When an edit is applied a snapshot of the target is stored every `interval` entries,
+/// and `go_to` restores the snapshot closest to the destination and only replays the
+/// remaining edits. At most `M` snapshots are kept and the oldest snapshot is dropped
+/// when more are added.
+///
+/// # Examples
+/// ```
+/// # use heapless::String;
+/// # use undo::{Add, Keyframes, Record};
+/// let mut target = String::<256>::new();
+/// let mut record = Record::<_, 32>::builder()
+///     .keyframes(Keyframes::<_, 8>::new(2))
+///     .build();
+///
+/// record.edit(&mut target, Add('a'));
+/// record.edit(&mut target, Add('b'));
+/// record.edit(&mut target, Add('c'));
+/// assert_eq!(record.keyframes().unwrap().len(), 1);
+///
+/// record.go_to(&mut target, 0);
+/// assert_eq!(target, "");
+/// // Restores the snapshot at 2 instead of redoing two edits.
+/// assert!(record.go_to(&mut target, 2).is_empty());
+/// assert_eq!(target, "ab");
+/// ```
+#[derive(Clone, Debug)]
+pub struct Keyframes<T, const M: usize> {
+    interval: NonZeroUsize,
+    frames: Deque<(usize, T), M>,
+}
+
+impl<T, const M: usize> Keyframes<T, M> {
+    /// Returns new keyframes that stores a snapshot every `interval` entries.
+    ///
+    /// # Panics
+    /// Panics if `interval` is `0`.
+    pub fn new(interval: usize) -> Keyframes<T, M> {
+        Keyframes {
+            interval: NonZeroUsize::new(interval).expect("interval can not be `0`"),
+            frames: Deque::new(),
+        }
+    }
+
+    /// Returns the number of entries between each snapshot.
+    pub fn interval(&self) -> usize {
+        self.interval.get()
+    }
+
+    /// Returns the number of stored snapshots.
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Returns `true` if no snapshots are stored.
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Removes all snapshots.
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    fn get(&self, id: usize) -> Option<&T> {
+        self.frames
+            .iter()
+            .find(|(frame, _)| *frame == id)
+            .map(|(_, snapshot)| snapshot)
+    }
+}
+
+impl<T: Clone, const M: usize> Keyframe<T> for Keyframes<T, M> {
+    fn is_due(&self, index: usize) -> bool {
+        index != 0 && index % self.interval == 0
+    }
+
+    /// Stores the snapshot, dropping the oldest snapshot if full.
+    fn insert(&mut self, id: usize, target: &T) {
+        if M == 0 || self.get(id).is_some() {
+            return;
+        }
+        if self.frames.is_full() {
+            self.frames.pop_front();
+        }
+        if self.frames.push_back((id, target.clone())).is_err() {
+            unreachable!("a snapshot was removed to make room");
+        }
+    }
+
+    fn restore(&self, id: usize, target: &mut T) -> bool {
+        let Some(snapshot) = self.get(id) else {
+            return false;
+        };
+        target.clone_from(snapshot);
+        true
+    }
+
+    fn ids(&self) -> impl Iterator<Item = usize> + '_ {
+        self.frames.iter().map(|&(id, _)| id)
+    }
+}
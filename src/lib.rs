@@ -16,6 +16,9 @@
 //! * [`Record`] provides basic stack based undo-redo functionality.
 //! * [`History`] provides full tree based undo-redo functionality.
 //! * Queue and checkpoint functionality is supported for both [`Record`] and [`History`].
+//! * Bugs in edit commands can be found by wrapping them in [`Checked`].
+//! * Changes can be written to a [`journal`] so the history can be recovered after a crash.
+//! * Long jumps can restore snapshots of the target instead of replaying every edit using [`Keyframes`].
 //! * The target can be marked as saved to disk and the user will be notified when it changes.
 //! * The amount of changes being tracked can be configured by the user so only the `N` most recent changes are stored.
 //! * Configurable display formatting using the display structures.
@@ -31,6 +34,7 @@
 //! |---------|---------|---------|-----------------------------------------------------------------|
 //! | std     | ✓       | alloc   | Enables the standard library.                                   |
 //! | alloc   |         |         | Enables the `alloc` crate.                                      |
+//! | binary  |         |         | Enables the compact binary encoding of the structures.          |
 //! | colored |         |         | Enables colored output when visualizing the display structures. |
 //! | serde   |         |         | Enables serialization and deserialization.                      |
 
@@ -43,22 +47,38 @@
 #[doc = include_str!("../README.md")]
 pub struct ReadmeDocTest;
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
 #[cfg(feature = "alloc")]
 mod add;
+mod checked;
+#[cfg(any(feature = "std", feature = "binary"))]
+mod codec;
+mod deque;
 mod entry;
 mod format;
 pub mod history;
+#[cfg(feature = "std")]
+pub mod journal;
+mod keyframes;
 pub mod record;
 mod socket;
 
 #[doc(hidden)]
 #[cfg(feature = "alloc")]
 pub use add::Add;
+pub use checked::Checked;
+#[cfg(any(feature = "std", feature = "binary"))]
+pub use codec::Codec;
 pub use entry::Entry;
 pub use history::History;
+pub use keyframes::{Keyframe, Keyframes};
 pub use record::Record;
 pub use socket::{Event, Slot};
 
+use core::convert::Infallible;
+use core::fmt;
 use format::Format;
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
@@ -92,6 +112,46 @@ pub trait Edit {
     }
 }
 
+/// The output of an [`Edit`] command that can report a failure.
+///
+/// Used by the queues to cancel all the queued operations if one of them fails.
+/// An edit that fails is assumed to not have changed the target.
+pub trait Outcome {
+    /// The output of a successful edit.
+    type Ok;
+    /// The error of a failed edit.
+    type Error;
+
+    /// Converts the output into a result.
+    fn into_result(self) -> Result<Self::Ok, Self::Error>;
+}
+
+impl Outcome for () {
+    type Ok = ();
+    type Error = Infallible;
+
+    fn into_result(self) -> Result<(), Infallible> {
+        Ok(())
+    }
+}
+
+impl<T, E> Outcome for Result<T, E> {
+    type Ok = T;
+    type Error = E;
+
+    fn into_result(self) -> Result<T, E> {
+        self
+    }
+}
+
+/// Reports the size of an [`Edit`] command.
+///
+/// Used by the `byte_budget` option on the builders to limit the memory used by the edits.
+pub trait EditSize {
+    /// Returns the size of the edit in bytes.
+    fn size(&self) -> usize;
+}
+
 /// Says if the [`Edit`] command have been merged with another command.
 #[derive(Copy, Clone, Debug)]
 pub enum Merged<E> {
@@ -109,6 +169,109 @@ pub enum Merged<E> {
     Annul,
 }
 
+/// An entry or branch that has been removed from the structures.
+///
+/// See [`record::Builder::on_evict`] for more information.
+#[derive(Clone, Debug)]
+pub enum Evicted<E, const N: usize> {
+    /// An entry that has been removed from the record.
+    Entry(Entry<E>),
+    /// A branch that has been removed from the history.
+    Branch(history::Branch<E, N>),
+}
+
+/// Handles the entries and branches that are removed from the structures.
+///
+/// Implemented for closures, so the hook can keep state.
+///
+/// # Examples
+/// ```
+/// # use heapless::String;
+/// # use undo::{Add, Evicted, Record};
+/// let mut evicted = 0;
+/// let mut target = String::<256>::new();
+/// let mut record = Record::<_, 32>::builder()
+///     .limit(1)
+///     .on_evict(|_: Evicted<Add<256>, 32>| evicted += 1)
+///     .build();
+///
+/// record.edit(&mut target, Add('a'));
+/// record.edit(&mut target, Add('b'));
+/// drop(record);
+/// assert_eq!(evicted, 1);
+/// ```
+pub trait Evict<E, const N: usize> {
+    /// Receives an entry or branch that has been removed from the structures.
+    fn on_evict(&mut self, evicted: Evicted<E, N>);
+}
+
+impl<E, const N: usize> Evict<E, N> for () {
+    fn on_evict(&mut self, _: Evicted<E, N>) {}
+}
+
+impl<E, const N: usize, F: FnMut(Evicted<E, N>)> Evict<E, N> for F {
+    fn on_evict(&mut self, evicted: Evicted<E, N>) {
+        self(evicted)
+    }
+}
+
+/// A broken invariant found by [`Record::validate`] or [`History::validate`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum InvariantError {
+    /// The limit is zero.
+    ZeroLimit,
+    /// The head is after the last entry.
+    HeadOutOfBounds,
+    /// The saved state is after the last entry.
+    SavedOutOfBounds,
+    /// The barrier is after the head.
+    BarrierAfterHead,
+    /// The root branch does not exist or is not empty.
+    InvalidRoot,
+    /// The id of the branch is used twice or is not below the number of branches.
+    InvalidId(usize),
+    /// The parent of the branch does not exist or is outside of the parent branch.
+    InvalidParent(usize),
+    /// The branch is not connected to the root branch.
+    Disconnected(usize),
+    /// The saved state is both in the record and in a branch.
+    SavedTwice,
+}
+
+impl fmt::Display for InvariantError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InvariantError::ZeroLimit => f.write_str("the limit is zero"),
+            InvariantError::HeadOutOfBounds => f.write_str("the head is after the last entry"),
+            InvariantError::SavedOutOfBounds => {
+                f.write_str("the saved state is after the last entry")
+            }
+            InvariantError::BarrierAfterHead => f.write_str("the barrier is after the head"),
+            InvariantError::InvalidRoot => {
+                f.write_str("the root branch does not exist or is not empty")
+            }
+            InvariantError::InvalidId(id) => {
+                write!(f, "the branch id {id} is used twice or is out of bounds")
+            }
+            InvariantError::InvalidParent(id) => {
+                write!(
+                    f,
+                    "the parent of branch {id} does not exist or is out of bounds"
+                )
+            }
+            InvariantError::Disconnected(id) => {
+                write!(f, "branch {id} is not connected to the root branch")
+            }
+            InvariantError::SavedTwice => {
+                f.write_str("the saved state is both in the record and in a branch")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for InvariantError {}
+
 /// A position in a history tree.
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -131,3 +294,19 @@ impl At {
         At::new(0, index)
     }
 }
+
+#[cfg(feature = "binary")]
+impl Codec for At {
+    fn encode(&self, buf: &mut [u8]) -> Option<usize> {
+        let mut w = codec::Writer::new(buf);
+        w.usize(self.root)?;
+        w.usize(self.index)?;
+        Some(w.len())
+    }
+
+    fn decode(bytes: &[u8]) -> Option<(Self, usize)> {
+        let mut r = codec::Reader::new(bytes);
+        let at = At::new(r.usize()?, r.usize()?);
+        Some((at, r.len()))
+    }
+}
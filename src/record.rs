@@ -6,20 +6,27 @@ mod display;
 mod queue;
 
 pub use builder::Builder;
-pub use checkpoint::Checkpoint;
+pub use checkpoint::{Checkpoint, Guard, Savepoint};
 pub use display::Display;
-pub use queue::Queue;
+pub use queue::{Queue, QueueOutput};
 
+#[cfg(feature = "binary")]
+use crate::codec::{Codec, Reader, Writer};
+use crate::deque::DequeExt;
 use crate::socket::{Slot, Socket};
-use crate::{Edit, Entry, Event, Merged};
+use crate::{Edit, EditSize, Entry, Event, Evict, Evicted, InvariantError, Keyframe, Merged};
 use core::fmt;
+use core::mem;
 use core::num::NonZeroUsize;
+use core::ops::{Bound, RangeBounds};
 use heapless::Deque;
 use heapless::String;
 use heapless::Vec;
 
 #[cfg(feature = "serde")]
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+#[cfg(feature = "std")]
+use std::time::{Duration, SystemTime};
 
 /// A linear record of [`Edit`] commands.
 ///
@@ -35,9 +42,10 @@ use serde::{Deserialize, Serialize};
 ///
 /// # Examples
 /// ```
+/// # use heapless::String;
 /// # use undo::{Add, Record};
-/// let mut target = String::new();
-/// let mut record = Record::new();
+/// let mut target = String::<256>::new();
+/// let mut record = Record::<_, 32>::new();
 ///
 /// record.edit(&mut target, Add('a'));
 /// record.edit(&mut target, Add('b'));
@@ -57,14 +65,60 @@ use serde::{Deserialize, Serialize};
 /// record.edit(&mut target, Add('d'));
 /// assert_eq!(target, "abd");
 /// ```
-#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(remote = "Self")
+)]
 #[derive(Clone, Debug)]
-pub struct Record<E, const N: usize, S = ()> {
+pub struct Record<E, const N: usize, S = (), H = (), K = ()> {
     limit: NonZeroUsize,
-    index: usize,
+    pub(crate) index: usize,
     pub(crate) saved: Option<usize>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) barrier: Option<usize>,
+    #[cfg_attr(feature = "serde", serde(skip, default = "Socket::default"))]
     pub(crate) socket: Socket<S>,
     pub(crate) entries: Deque<Entry<E>, N>,
+    #[cfg_attr(feature = "serde", serde(skip, default = "Option::default"))]
+    evict: Option<H>,
+    #[cfg_attr(feature = "serde", serde(skip, default = "Option::default"))]
+    keyframes: Option<K>,
+    /// The id that is given to the next new state, see [`Record::new_entry`].
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) next_id: usize,
+    #[cfg_attr(feature = "serde", serde(skip, default = "Option::default"))]
+    budget: Option<Budget<E>>,
+    /// The total size of the edits that have not been evicted, if the budget is set.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) size: usize,
+    #[cfg(feature = "std")]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    max_age: Option<Duration>,
+}
+
+/// The byte budget and the function used to get the size of an edit.
+pub(crate) type Budget<E> = (usize, fn(&E) -> usize);
+
+/// The states that were removed from the record.
+#[derive(Copy, Clone, Debug)]
+pub(crate) enum Removed {
+    /// The `by` oldest states were removed.
+    Front(usize),
+    /// The state at `at` was removed and the following states were moved `by` steps towards the start.
+    At { at: usize, by: usize },
+}
+
+impl Removed {
+    /// Returns the new index of the state, or `None` if the state was removed.
+    pub fn shift(self, index: usize) -> Option<usize> {
+        match self {
+            Removed::Front(by) => index.checked_sub(by),
+            Removed::At { at, by } if index > at => Some(index - by),
+            Removed::At { at, .. } if index == at => None,
+            Removed::At { .. } => Some(index),
+        }
+    }
 }
 
 impl<E, const N: usize> Record<E, N> {
@@ -74,16 +128,16 @@ impl<E, const N: usize> Record<E, N> {
     }
 }
 
-impl<E, const N: usize, S> Record<E, N, S> {
+impl<E, const N: usize, S, H, K> Record<E, N, S, H, K> {
     /// Returns a new record builder.
-    pub fn builder() -> Builder<E, N, S> {
+    pub fn builder() -> Builder<E, N, S, H, K> {
         Builder::default()
     }
 
-    /// Reserves capacity for at least `additional` more edits.
-    ///
-    /// # Panics
-    /// Panics if the new capacity exceeds `isize::MAX` bytes.
+    // Reserves capacity for at least `additional` more edits.
+    //
+    // # Panics
+    // Panics if the new capacity exceeds `isize::MAX` bytes.
     // pub fn reserve(&mut self, additional: usize) {
     //     self.entries.reserve(additional);
     // }
@@ -93,7 +147,7 @@ impl<E, const N: usize, S> Record<E, N, S> {
         self.entries.capacity()
     }
 
-    /// Shrinks the capacity of the record as much as possible.
+    // Shrinks the capacity of the record as much as possible.
     // pub fn shrink_to_fit(&mut self) {
     //     self.entries.shrink_to_fit();
     // }
@@ -113,6 +167,23 @@ impl<E, const N: usize, S> Record<E, N, S> {
         self.limit.get()
     }
 
+    /// Checks that the internal invariants of the record hold.
+    ///
+    /// This can be used to detect a corrupted record, e.g. after deserialization.
+    /// The number of entries is not checked against the limit,
+    /// since pinned entries can keep the record above it.
+    pub fn validate(&self) -> Result<(), InvariantError> {
+        if self.index > self.len() {
+            Err(InvariantError::HeadOutOfBounds)
+        } else if self.saved.is_some_and(|saved| saved > self.len()) {
+            Err(InvariantError::SavedOutOfBounds)
+        } else if self.barrier.is_some_and(|barrier| barrier > self.index) {
+            Err(InvariantError::BarrierAfterHead)
+        } else {
+            Ok(())
+        }
+    }
+
     /// Sets how the event should be handled when the state changes.
     pub fn connect(&mut self, slot: S) -> Option<S> {
         self.socket.connect(Some(slot))
@@ -123,9 +194,33 @@ impl<E, const N: usize, S> Record<E, N, S> {
         self.socket.disconnect()
     }
 
+    /// Returns the total size of the edits in the record.
+    pub fn total_size(&self) -> usize
+    where
+        E: EditSize,
+    {
+        self.entries().map(|entry| entry.as_ref().size()).sum()
+    }
+
+    /// Returns the size of the entry if the budget is set, otherwise `0`.
+    pub(crate) fn size_of(&self, entry: &Entry<E>) -> usize {
+        self.budget
+            .map_or(0, |(_, size_of)| size_of(entry.as_ref()))
+    }
+
+    /// Returns `true` if the edits use more than the budget.
+    pub(crate) fn is_over_budget(&self) -> bool {
+        self.budget.is_some_and(|(budget, _)| self.size > budget)
+    }
+
+    /// Removes the size of an entry that is dropped without being evicted.
+    pub(crate) fn discard(&mut self, entry: Entry<E>) {
+        self.size -= self.size_of(&entry);
+    }
+
     /// Returns `true` if the record can undo.
     pub fn can_undo(&self) -> bool {
-        self.index > 0
+        self.index > self.barrier.unwrap_or(0)
     }
 
     /// Returns `true` if the record can redo.
@@ -133,6 +228,11 @@ impl<E, const N: usize, S> Record<E, N, S> {
         self.index < self.len()
     }
 
+    /// Returns the position of the barrier.
+    pub fn barrier(&self) -> Option<usize> {
+        self.barrier
+    }
+
     /// Returns `true` if the target is in a saved state, `false` otherwise.
     pub fn is_saved(&self) -> bool {
         self.saved == Some(self.index)
@@ -150,7 +250,7 @@ impl<E, const N: usize, S> Record<E, N, S> {
 
     /// Returns the entry at the index.
     pub fn get_entry(&self, index: usize) -> Option<&Entry<E>> {
-        self.entries.iter().nth(index)
+        self.entries.get(index)
     }
 
     /// Returns an iterator over the entries.
@@ -159,20 +259,96 @@ impl<E, const N: usize, S> Record<E, N, S> {
     }
 
     /// Returns a queue.
-    pub fn queue<const M: usize>(&mut self) -> Queue<E, N, M, S> {
+    pub fn queue<const M: usize>(&mut self) -> Queue<'_, E, N, M, S, H, K> {
         Queue::from(self)
     }
 
     /// Returns a checkpoint.
-    pub fn checkpoint<const M: usize>(&mut self) -> Checkpoint<E, N, M, S> {
+    pub fn checkpoint<const M: usize>(&mut self) -> Checkpoint<'_, E, N, M, S, H, K> {
         Checkpoint::from(self)
     }
 
     /// Returns a structure for configurable formatting of the record.
-    pub fn display<const SIZE: usize>(&self) -> Display<E, N, SIZE, S> {
+    pub fn display<const SIZE: usize>(&self) -> Display<'_, E, N, SIZE, S, H, K> {
         Display::from(self)
     }
 
+    /// Returns the keyframes if they are set.
+    pub fn keyframes(&self) -> Option<&K> {
+        self.keyframes.as_ref()
+    }
+
+    /// Returns a new entry for the edit, with an id that is larger than the ids of the other states.
+    ///
+    /// The ids increase from the start of the record towards the end, and from a state to the
+    /// states in the branches split off from it, so a state can be found with a binary search.
+    pub(crate) fn new_entry(&mut self, edit: E) -> Entry<E> {
+        let mut entry = Entry::new(edit);
+        entry.set_id(self.new_id());
+        entry
+    }
+
+    pub(crate) fn new_id(&mut self) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    /// Gives the entries new ids in order, since the ids are not serialized.
+    #[cfg(any(feature = "alloc", feature = "serde", feature = "binary"))]
+    pub(crate) fn renew_ids(&mut self) {
+        for i in 0..self.len() {
+            let id = self.new_id();
+            self.entries.get_mut(i).expect("in the range").set_id(id);
+        }
+    }
+
+    /// Returns the index of the entry that produces the state with the id.
+    pub(crate) fn position_of(&self, id: usize) -> Option<usize> {
+        self.entries.binary_search_by_key(id, Entry::id)
+    }
+
+    /// Pins the entry at the index so it is not evicted when the limit is reached.
+    ///
+    /// When the oldest edit is pinned, the oldest edit that is not pinned is merged with
+    /// the edit after it instead. If they can not be merged the limit is exceeded and
+    /// [`Event::LimitExceeded`] is emitted, and the edits can be combined with [`Record::squash_with`].
+    /// The oldest edit is still removed if the record is at its capacity.
+    ///
+    /// Returns `false` if there is no entry at the index.
+    pub fn pin(&mut self, index: usize) -> bool {
+        self.set_pinned(index, true)
+    }
+
+    /// Unpins the entry at the index.
+    ///
+    /// Returns `false` if there is no entry at the index.
+    pub fn unpin(&mut self, index: usize) -> bool {
+        self.set_pinned(index, false)
+    }
+
+    fn set_pinned(&mut self, index: usize, pinned: bool) -> bool {
+        let Some(entry) = self.entries.get_mut(index) else {
+            return false;
+        };
+        entry.set_pinned(pinned);
+        true
+    }
+
+    /// Returns the time before which the edits are expired at `now`.
+    #[cfg(feature = "std")]
+    pub(crate) fn expires_before(&self, now: SystemTime) -> Option<SystemTime> {
+        self.max_age.and_then(|max_age| now.checked_sub(max_age))
+    }
+
+    /// Returns the number of the oldest edits that were made before `time`.
+    #[cfg(feature = "std")]
+    pub(crate) fn count_before(&self, time: SystemTime) -> usize {
+        self.entries()
+            .take_while(|entry| entry.st_of_edit() < time)
+            .count()
+    }
+
     /// Remove all elements after the index.
     pub(crate) fn rm_tail(&mut self) -> (Deque<Entry<E>, N>, Option<usize>) {
         // Remove the saved state if it will be split off.
@@ -184,13 +360,45 @@ impl<E, const N: usize, S> Record<E, N, S> {
 
         let mut tail = Deque::new();
         for _ in self.index..self.entries.len() {
-            let _ = tail.push_back(self.entries.pop_back().expect("in the range"));
+            let _ = tail.push_front(self.entries.pop_back().expect("in the range"));
         }
         (tail, rm_saved)
     }
 }
 
-impl<E, const N: usize, S: Slot> Record<E, N, S> {
+impl<E, const N: usize, S, H: Evict<E, N>, K> Record<E, N, S, H, K> {
+    /// Passes the entry or branch to the eviction hook if it is set.
+    pub(crate) fn evict(&mut self, evicted: Evicted<E, N>) {
+        self.size -= match &evicted {
+            Evicted::Entry(entry) => self.size_of(entry),
+            Evicted::Branch(branch) => branch.entries().map(|entry| self.size_of(entry)).sum(),
+        };
+        if let Some(hook) = &mut self.evict {
+            hook.on_evict(evicted);
+        }
+    }
+
+    /// Passes the entries to the eviction hook if it is set.
+    pub(crate) fn evict_entries(&mut self, entries: impl IntoIterator<Item = Entry<E>>) {
+        for entry in entries {
+            self.evict(Evicted::Entry(entry));
+        }
+    }
+
+    /// Removes the `count` oldest entries, which must not be after the index.
+    pub(crate) fn rm_front(&mut self, count: usize) -> Removed {
+        for _ in 0..count {
+            let entry = self.entries.pop_front().expect("in the range");
+            self.evict(Evicted::Entry(entry));
+        }
+        self.index -= count;
+        self.saved = self.saved.and_then(|saved| saved.checked_sub(count));
+        self.barrier = self.barrier.map(|barrier| barrier.saturating_sub(count));
+        Removed::Front(count)
+    }
+}
+
+impl<E, const N: usize, S: Slot, H: Evict<E, N>, K> Record<E, N, S, H, K> {
     /// Marks the target as currently being in a saved.
     pub fn set_saved(&mut self) {
         let was_saved = self.is_saved();
@@ -205,105 +413,351 @@ impl<E, const N: usize, S: Slot> Record<E, N, S> {
         self.socket.emit_if(was_saved, || Event::Saved(false));
     }
 
+    /// Sets a barrier at the current position that the record can not undo past.
+    ///
+    /// This is useful after actions that can not be reversed,
+    /// without having to remove the edits with [`Record::clear`].
+    pub fn set_barrier(&mut self) {
+        self.coalesce(|record| record.barrier = Some(record.index));
+    }
+
+    /// Removes the barrier.
+    pub fn clear_barrier(&mut self) {
+        self.coalesce(|record| record.barrier = None);
+    }
+
     /// Removes all edits from the record without undoing them.
     pub fn clear(&mut self) {
+        let (entries, _, _, _) = self.rm_entries();
+        self.evict_entries(entries);
+    }
+
+    /// Removes all edits after the head without undoing them.
+    ///
+    /// The saved state is cleared if it was removed.
+    pub fn clear_redo(&mut self) {
+        let (tail, _) = self.coalesce(|record| record.rm_tail());
+        self.evict_entries(tail);
+    }
+
+    /// Removes the `count` oldest edits from the record without undoing them.
+    ///
+    /// Only the edits before the head can be removed, so `count` is clamped to [`Record::head`].
+    /// The saved state is cleared if it was removed.
+    ///
+    /// Returns the number of edits that were removed.
+    pub fn truncate_front(&mut self, count: usize) -> usize {
+        let count = count.min(self.index);
+        self.coalesce(|record| record.rm_front(count));
+        count
+    }
+
+    /// Removes the edits that were made before `time` without undoing them.
+    ///
+    /// See [`Record::truncate_front`] for more information.
+    #[cfg(feature = "std")]
+    pub fn prune_before(&mut self, time: SystemTime) -> usize {
+        self.truncate_front(self.count_before(time))
+    }
+
+    /// Removes the edits that are older than the max age without undoing them.
+    ///
+    /// The edits after the head are removed too if the oldest of them is expired,
+    /// since they can not be redone without it. The saved state is cleared if it was removed.
+    /// This is also done each time a new edit is pushed to the record, but the active edit is kept.
+    ///
+    /// Returns the number of edits that were removed.
+    #[cfg(feature = "std")]
+    pub fn enforce_retention(&mut self, now: SystemTime) -> usize {
+        let Some(time) = self.expires_before(now) else {
+            return 0;
+        };
+        let count = self.count_before(time);
+        let len = self.len();
+        self.coalesce(|record| {
+            if count > record.index {
+                let (tail, _) = record.rm_tail();
+                record.evict_entries(tail);
+            }
+            record.rm_front(count.min(record.index));
+        });
+        len - self.len()
+    }
+
+    /// Removes the oldest edits that are older than the max age, but keeps the active edit.
+    ///
+    /// This is only done when a new edit is pushed, since the edits that
+    /// are moved between the branches of a history keep their age.
+    pub(crate) fn expire(&mut self) -> Option<Removed> {
+        #[cfg(feature = "std")]
+        if let Some(time) = self.expires_before(SystemTime::now()) {
+            let count = self.count_before(time).min(self.index.saturating_sub(1));
+            if count > 0 {
+                return Some(self.coalesce(|record| record.rm_front(count)));
+            }
+        }
+        None
+    }
+
+    /// Removes all entries and returns them together with the old index, saved state and barrier.
+    pub(crate) fn rm_entries(
+        &mut self,
+    ) -> (Deque<Entry<E>, N>, usize, Option<usize>, Option<usize>) {
+        let index = self.index;
+        let saved = self.saved;
+        let barrier = self.barrier;
+        let entries = self.coalesce(|record| {
+            record.saved = record.is_saved().then_some(0);
+            record.barrier = None;
+            record.index = 0;
+            mem::take(&mut record.entries)
+        });
+        (entries, index, saved, barrier)
+    }
+
+    /// Calls `f` with the slot disconnected and emits a single event
+    /// for each of the states that changed.
+    pub(crate) fn coalesce<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
         let old_index = self.index;
+        let old_barrier = self.barrier;
         let could_undo = self.can_undo();
         let could_redo = self.can_redo();
-        self.entries.clear();
-        self.saved = self.is_saved().then_some(0);
-        self.index = 0;
-        self.socket.emit_if(could_undo, || Event::Undo(false));
-        self.socket.emit_if(could_redo, || Event::Redo(false));
-        self.socket.emit_if(old_index != 0, || Event::Index(0));
+        let was_saved = self.is_saved();
+        // Temporarily remove slot so they are not called each iteration.
+        let slot = self.socket.disconnect();
+        let result = f(self);
+        let can_undo = self.can_undo();
+        let can_redo = self.can_redo();
+        let is_saved = self.is_saved();
+        self.socket.connect(slot);
+        self.socket
+            .emit_if(could_undo != can_undo, || Event::Undo(can_undo));
+        self.socket
+            .emit_if(could_redo != can_redo, || Event::Redo(can_redo));
+        self.socket
+            .emit_if(was_saved != is_saved, || Event::Saved(is_saved));
+        self.socket
+            .emit_if(old_index != self.index, || Event::Index(self.index));
+        self.socket
+            .emit_if(old_barrier != self.barrier, || Event::Barrier(self.barrier));
+        result
     }
 }
 
-impl<E: Edit, const N: usize, S: Slot> Record<E, N, S> {
+impl<E: Edit, const N: usize, S: Slot, H: Evict<E, N>, K: Keyframe<E::Target>>
+    Record<E, N, S, H, K>
+{
+    /// Returns a checkpoint guard that cancels the changes when dropped.
+    ///
+    /// See [`Guard`] for more information.
+    pub fn guard<'a, const M: usize>(
+        &'a mut self,
+        target: &'a mut E::Target,
+    ) -> Guard<'a, E, N, M, S, H, K> {
+        Guard::new(self, target)
+    }
+
     /// Pushes the edit on top of the record and executes its [`Edit::edit`] method.
+    ///
+    /// A snapshot of the target is stored if the keyframes are set and a keyframe is due.
     pub fn edit(&mut self, target: &mut E::Target, edit: E) -> E::Output {
-        let (output, _, _, _) = self.edit_and_push(target, Entry::new(edit));
+        let entry = self.new_entry(edit);
+        let (output, _, tail, _) = self.edit_and_push(target, entry);
+        self.evict_entries(tail);
+        self.snapshot(target);
         output
     }
 
+    /// Returns the output of the edit, the states that were removed by the limit or budget,
+    /// the discarded tail, and the saved state if it was in the tail.
     pub(crate) fn edit_and_push(
         &mut self,
         target: &mut E::Target,
         mut entry: Entry<E>,
-    ) -> (E::Output, bool, Deque<Entry<E>, N>, Option<usize>) {
+    ) -> (
+        E::Output,
+        Vec<Removed, N>,
+        Deque<Entry<E>, N>,
+        Option<usize>,
+    ) {
         let output = entry.edit(target);
-        let (merged_or_annulled, tail, rm_saved) = self.push(entry);
-        (output, merged_or_annulled, tail, rm_saved)
+        let (mut removed, tail, rm_saved) = self.push(entry, true);
+        removed.extend(self.expire());
+        (output, removed, tail, rm_saved)
     }
 
     pub(crate) fn redo_and_push(
         &mut self,
         target: &mut E::Target,
         mut entry: Entry<E>,
-    ) -> (E::Output, bool, Deque<Entry<E>, N>, Option<usize>) {
+    ) -> (
+        E::Output,
+        Vec<Removed, N>,
+        Deque<Entry<E>, N>,
+        Option<usize>,
+    ) {
         let output = entry.redo(target);
-        let (merged_or_annulled, tail, rm_saved) = self.push(entry);
-        (output, merged_or_annulled, tail, rm_saved)
+        let (removed, tail, rm_saved) = self.push(entry, false);
+        (output, removed, tail, rm_saved)
     }
 
-    fn push(&mut self, entry: Entry<E>) -> (bool, Deque<Entry<E>, N>, Option<usize>) {
+    /// Pushes the entry, which must already have been applied, on top of the record.
+    ///
+    /// The entry is only merged with the previous entry if `merge` is `true`.
+    pub(crate) fn push(
+        &mut self,
+        entry: Entry<E>,
+        merge: bool,
+    ) -> (Vec<Removed, N>, Deque<Entry<E>, N>, Option<usize>) {
         let old_index = self.index;
+        let old_barrier = self.barrier;
         let could_undo = self.can_undo();
         let could_redo = self.can_redo();
         let was_saved = self.is_saved();
 
         let (tail, rm_saved) = self.rm_tail();
-        // Try to merge unless the target is in a saved state.
+        let last_size = self.entries.back().map_or(0, |last| self.size_of(last));
+        // Try to merge unless the target is in a saved state or the last edit is behind the barrier.
         let merged = match self.entries.back_mut() {
-            Some(last) if !was_saved => last.merge(entry),
+            Some(last) if merge && !was_saved && self.barrier != Some(self.index) => {
+                last.merge(entry)
+            }
             _ => Merged::No(entry),
         };
 
-        let merged_or_annulled = match merged {
-            Merged::Yes => true,
+        let mut removed = Vec::new();
+        let mut exceeded = false;
+        match merged {
+            Merged::Yes => {
+                let last = self.entries.back().expect("in the range");
+                self.size = self.size - last_size + self.size_of(last);
+            }
             Merged::Annul => {
-                self.entries.pop_back();
+                let entry = self.entries.pop_back().expect("in the range");
+                self.evict(Evicted::Entry(entry));
                 self.index -= 1;
-                true
             }
             Merged::No(entry) => {
-                // If limit is reached, pop off the first edit command.
-                if self.limit() == self.index {
-                    self.entries.pop_front();
-                    self.saved = self.saved.and_then(|saved| saved.checked_sub(1));
-                } else {
-                    self.index += 1;
+                // If limit is reached, remove the oldest states until there is room for the edit.
+                while self.len() >= self.limit().min(self.capacity()) {
+                    let full = self.len() == self.capacity();
+                    let Some(rm) = self.rm_oldest(full) else {
+                        exceeded = true;
+                        break;
+                    };
+                    removed.extend(Some(rm));
                 }
+                self.index += 1;
+                self.size += self.size_of(&entry);
                 let _ = self.entries.push_back(entry);
-                false
             }
-        };
+        }
+
+        // Remove the oldest states until the edits fit in the budget.
+        while self.entries.len() > 1 && self.is_over_budget() {
+            let Some(rm) = self.rm_oldest(false) else {
+                break;
+            };
+            let _ = removed.push(rm);
+        }
 
         self.socket.emit_if(could_redo, || Event::Redo(false));
         self.socket.emit_if(!could_undo, || Event::Undo(true));
         self.socket.emit_if(was_saved, || Event::Saved(false));
         self.socket
             .emit_if(old_index != self.index, || Event::Index(self.index));
-        (merged_or_annulled, tail, rm_saved)
+        self.socket
+            .emit_if(old_barrier != self.barrier, || Event::Barrier(self.barrier));
+        self.socket.emit_if(exceeded, || Event::LimitExceeded);
+        (removed, tail, rm_saved)
+    }
+
+    /// Removes the oldest edit, or merges the oldest unpinned edits if the oldest edit is pinned.
+    ///
+    /// If the edits can not be merged the pinned edit is kept and `None` is returned,
+    /// unless the record is `full`.
+    ///
+    /// Returns the states that were removed.
+    fn rm_oldest(&mut self, full: bool) -> Option<Removed> {
+        if !self.entries.front().is_some_and(Entry::is_pinned) {
+            return Some(self.rm_front(1));
+        }
+        match self.merge_oldest() {
+            Some(removed) => Some(removed),
+            None if full => Some(self.rm_front(1)),
+            None => None,
+        }
+    }
+
+    /// Merges the oldest edit that is not pinned with the edit after it
+    /// and returns the states that were removed.
+    ///
+    /// Returns `None` if the edit after it is pinned or the edits can not be merged.
+    fn merge_oldest(&mut self) -> Option<Removed> {
+        let first = self.entries.iter().position(|entry| !entry.is_pinned())?;
+        if self.entries.get(first + 1)?.is_pinned() {
+            return None;
+        }
+        // Only the entries after the merged edits are moved to take out the next edit.
+        let mut tail = Vec::<_, N>::new();
+        while self.entries.len() > first + 2 {
+            let _ = tail.push(self.entries.pop_back().expect("in the range"));
+        }
+        let next = self.entries.pop_back().expect("in the range");
+        let size = self.size_of(&next);
+        let last_size = self.size_of(self.entries.get(first).expect("in the range"));
+        let removed = match self
+            .entries
+            .get_mut(first)
+            .expect("in the range")
+            .merge(next)
+        {
+            Merged::Yes => {
+                let last = self.entries.get(first).expect("in the range");
+                self.size = self.size - size - last_size + self.size_of(last);
+                Some((first + 1, 1))
+            }
+            Merged::Annul => {
+                self.size -= size;
+                let entry = self.entries.pop_back().expect("in the range");
+                self.evict(Evicted::Entry(entry));
+                Some((first + 1, 2))
+            }
+            Merged::No(next) => {
+                let _ = self.entries.push_back(next);
+                None
+            }
+        };
+        while let Some(entry) = tail.pop() {
+            let _ = self.entries.push_back(entry);
+        }
+        let (at, by) = removed?;
+        let removed = Removed::At { at, by };
+        self.index -= by;
+        self.saved = self.saved.and_then(|saved| removed.shift(saved));
+        // A barrier in the removed states is moved to the state that replaced them.
+        self.barrier = self.barrier.map(|barrier| match barrier {
+            barrier if barrier >= at => barrier.saturating_sub(by).max(at + 1 - by),
+            barrier => barrier,
+        });
+        Some(removed)
     }
 
     /// Calls the [`Edit::undo`] method for the active edit and sets
     /// the previous one as the new active one.
     pub fn undo(&mut self, target: &mut E::Target) -> Option<E::Output> {
         self.can_undo().then(|| {
-            let old_index = self.index;
+            let could_redo = self.can_redo();
             let was_saved = self.is_saved();
             let output = self
                 .entries
-                .iter_mut()
-                .nth(self.index - 1)
+                .get_mut(self.index - 1)
                 .expect("in the range")
                 .undo(target);
             self.index -= 1;
             let is_saved = self.is_saved();
-            self.socket.emit_if(old_index == 1, || Event::Undo(false));
-            self.socket
-                .emit_if(old_index == self.entries.len(), || Event::Redo(true));
+            self.socket.emit_if(!self.can_undo(), || Event::Undo(false));
+            self.socket.emit_if(!could_redo, || Event::Redo(true));
             self.socket
                 .emit_if(was_saved != is_saved, || Event::Saved(is_saved));
             self.socket.emit(|| Event::Index(self.index));
@@ -315,19 +769,17 @@ impl<E: Edit, const N: usize, S: Slot> Record<E, N, S> {
     /// the next one as the new active one.
     pub fn redo(&mut self, target: &mut E::Target) -> Option<E::Output> {
         self.can_redo().then(|| {
-            let old_index = self.index;
+            let could_undo = self.can_undo();
             let was_saved = self.is_saved();
             let output = self
                 .entries
-                .iter_mut()
-                .nth(self.index)
+                .get_mut(self.index)
                 .expect("in the range")
                 .redo(target);
             self.index += 1;
             let is_saved = self.is_saved();
-            self.socket.emit_if(old_index == 0, || Event::Undo(true));
-            self.socket
-                .emit_if(old_index == self.len() - 1, || Event::Redo(false));
+            self.socket.emit_if(!could_undo, || Event::Undo(true));
+            self.socket.emit_if(!self.can_redo(), || Event::Redo(false));
             self.socket
                 .emit_if(was_saved != is_saved, || Event::Saved(is_saved));
             self.socket.emit(|| Event::Index(self.index));
@@ -335,6 +787,88 @@ impl<E: Edit, const N: usize, S: Slot> Record<E, N, S> {
         })
     }
 
+    /// Replaces the active edit without pushing a new entry on the record.
+    ///
+    /// The active edit is undone, then `f` is called so it can be modified or
+    /// replaced, and then the edit is applied again using [`Edit::edit`].
+    /// The redo tail is kept, but the saved state is cleared if it depended
+    /// on the amended edit.
+    ///
+    /// Returns `None` if there is no active edit or the barrier is at the head,
+    /// since the active edit can not be undone past the barrier.
+    ///
+    /// # Examples
+    /// ```
+    /// # use heapless::String;
+    /// # use undo::{Add, Record};
+    /// let mut target = String::<256>::new();
+    /// let mut record = Record::<_, 32>::new();
+    /// record.edit(&mut target, Add('a'));
+    /// record.edit(&mut target, Add('b'));
+    ///
+    /// record.amend(&mut target, |edit| *edit = Add('c'));
+    /// assert_eq!(target, "ac");
+    /// assert_eq!(record.len(), 2);
+    /// ```
+    pub fn amend(&mut self, target: &mut E::Target, f: impl FnOnce(&mut E)) -> Option<E::Output> {
+        if self.barrier == Some(self.index) {
+            return None;
+        }
+        let index = self.index.checked_sub(1)?;
+        let was_saved = self.is_saved();
+        let entry = self.entries.get_mut(index).expect("in the range");
+        let size = self
+            .budget
+            .map_or(0, |(_, size_of)| size_of(entry.as_ref()));
+        let output = entry.amend(target, f);
+        let entry = self.entries.get(index).expect("in the range");
+        self.size = self.size - size + self.size_of(entry);
+        // The amended state and the states after it have changed.
+        for i in index..self.len() {
+            let id = self.new_id();
+            self.entries.get_mut(i).expect("in the range").set_id(id);
+        }
+        self.saved = self.saved.filter(|&saved| saved <= index);
+        self.socket.emit_if(was_saved, || Event::Saved(false));
+        Some(output)
+    }
+
+    /// Calls [`Record::undo`] up to `n` times.
+    ///
+    /// The events are only emitted once, like in [`Record::go_to`].
+    /// Returns the outputs of the edits that were undone,
+    /// which are fewer than `n` if the start of the record or the barrier was reached.
+    /// The keyframes are not used, so there is one output for each step.
+    pub fn undo_n(&mut self, target: &mut E::Target, n: usize) -> Vec<E::Output, N> {
+        let index = self.index.saturating_sub(n).max(self.barrier.unwrap_or(0));
+        self.step_to(target, index, false)
+    }
+
+    /// Calls [`Record::redo`] up to `n` times.
+    ///
+    /// The events are only emitted once, like in [`Record::go_to`].
+    /// Returns the outputs of the edits that were redone,
+    /// which are fewer than `n` if the end of the record was reached.
+    /// The keyframes are not used, so there is one output for each step.
+    pub fn redo_n(&mut self, target: &mut E::Target, n: usize) -> Vec<E::Output, N> {
+        let index = self.index.saturating_add(n).min(self.len());
+        self.step_to(target, index, false)
+    }
+
+    /// Undoes all the edits in the record, or up to the barrier if it is set.
+    ///
+    /// See [`Record::undo_n`] for more information.
+    pub fn undo_all(&mut self, target: &mut E::Target) -> Vec<E::Output, N> {
+        self.step_to(target, self.barrier.unwrap_or(0), false)
+    }
+
+    /// Redoes all the edits in the record.
+    ///
+    /// See [`Record::redo_n`] for more information.
+    pub fn redo_all(&mut self, target: &mut E::Target) -> Vec<E::Output, N> {
+        self.step_to(target, self.len(), false)
+    }
+
     /// Revert the changes done to the target since the saved state.
     pub fn revert(&mut self, target: &mut E::Target) -> Vec<E::Output, N> {
         self.saved
@@ -342,46 +876,262 @@ impl<E: Edit, const N: usize, S: Slot> Record<E, N, S> {
     }
 
     /// Repeatedly calls [`Edit::undo`] or [`Edit::redo`] until the edit at `index` is reached.
+    ///
+    /// If the keyframes are set, the snapshot closest to `index` is restored first if that is
+    /// fewer steps away, and only the outputs of the edits that were replayed are returned.
+    /// Does nothing if `index` is before the barrier.
     pub fn go_to(&mut self, target: &mut E::Target, index: usize) -> Vec<E::Output, N> {
-        if self.index == index || index > self.len() {
+        self.step_to(target, index, true)
+    }
+
+    /// Moves to `index`, restoring the closest snapshot first only if `keyframes` is `true`.
+    fn step_to(
+        &mut self,
+        target: &mut E::Target,
+        index: usize,
+        keyframes: bool,
+    ) -> Vec<E::Output, N> {
+        if self.index == index || index > self.len() || Some(index) < self.barrier {
             return Vec::new();
         }
 
-        let could_undo = self.can_undo();
-        let could_redo = self.can_redo();
-        let was_saved = self.is_saved();
-        // Temporarily remove slot so they are not called each iteration.
-        let slot = self.socket.disconnect();
-        // Decide if we need to undo or redo to reach index.
-        let undo_or_redo = if index > self.index {
-            Record::redo
-        } else {
-            Record::undo
-        };
+        let outputs = self.coalesce(|record| {
+            if let Some((start, id)) = record.closest_keyframe(index).filter(|_| keyframes) {
+                let keyframes = record.keyframes.as_ref().expect("keyframes are set");
+                if start.abs_diff(index) < record.index.abs_diff(index)
+                    && keyframes.restore(id, target)
+                {
+                    record.index = start;
+                }
+            }
+
+            // Decide if we need to undo or redo to reach index.
+            let undo_or_redo = if index > record.index {
+                Record::redo
+            } else {
+                Record::undo
+            };
+
+            let mut outputs = Vec::<_, N>::new();
+            while record.index != index {
+                let output = undo_or_redo(record, target).unwrap();
+                let _ = outputs.push(output);
+            }
+            outputs
+        });
+        self.snapshot(target);
+        outputs
+    }
+
+    /// Returns the index and id of the state closest to `index` that has a snapshot.
+    fn closest_keyframe(&self, index: usize) -> Option<(usize, usize)> {
+        let barrier = self.barrier.unwrap_or(0);
+        self.keyframes
+            .as_ref()?
+            .ids()
+            .filter_map(|id| Some((self.position_of(id)? + 1, id)))
+            .filter(|&(start, _)| start >= barrier)
+            .min_by_key(|&(start, _)| start.abs_diff(index))
+    }
 
-        let mut outputs = Vec::<_, N>::new();
-        while self.index != index {
-            let output = undo_or_redo(self, target).unwrap();
-            let _ = outputs.push(output);
+    /// Stores a snapshot of the target at the head if the keyframes are set and a keyframe is due.
+    pub(crate) fn snapshot(&mut self, target: &E::Target) {
+        let Some(keyframes) = &mut self.keyframes else {
+            return;
+        };
+        if keyframes.is_due(self.index) {
+            let id = self.entries.get(self.index - 1).expect("in the range").id();
+            keyframes.insert(id, target);
         }
+    }
 
-        let can_undo = self.can_undo();
-        let can_redo = self.can_redo();
-        let is_saved = self.is_saved();
-        self.socket.connect(slot);
-        self.socket
-            .emit_if(could_undo != can_undo, || Event::Undo(can_undo));
-        self.socket
-            .emit_if(could_redo != can_redo, || Event::Redo(can_redo));
-        self.socket
-            .emit_if(was_saved != is_saved, || Event::Saved(is_saved));
-        self.socket.emit(|| Event::Index(self.index));
+    /// Applies the edits before the head to a target in the state before the oldest entry.
+    ///
+    /// Calls [`Edit::redo`] on a clone of each entry in order, so the record is not changed.
+    /// This can be used to rebuild a target from a record that was loaded without it.
+    ///
+    /// The target must be in the state it was in before the oldest entry was applied.
+    /// This is the initial state only if no entries have been removed from the front of
+    /// the record, either by the limit, the budget, the retention settings or
+    /// [`Record::truncate_front`], otherwise the target must be in the state after the
+    /// removed edits.
+    ///
+    /// # Examples
+    /// ```
+    /// # use heapless::String;
+    /// # use undo::{Add, Record};
+    /// let mut target = String::<256>::new();
+    /// let mut record = Record::<_, 32>::new();
+    /// record.edit(&mut target, Add('a'));
+    /// record.edit(&mut target, Add('b'));
+    /// record.edit(&mut target, Add('c'));
+    /// record.undo(&mut target);
+    ///
+    /// let mut fresh = String::<256>::new();
+    /// record.replay(&mut fresh);
+    /// assert_eq!(fresh, "ab");
+    /// assert_eq!(record.head(), 2);
+    /// ```
+    pub fn replay(&self, target: &mut E::Target) -> Vec<E::Output, N>
+    where
+        E: Clone,
+    {
+        self.replay_until(target, self.index)
+    }
 
+    /// Calls [`Edit::redo`] on a clone of each entry before `index`.
+    pub(crate) fn replay_until(&self, target: &mut E::Target, index: usize) -> Vec<E::Output, N>
+    where
+        E: Clone,
+    {
+        let mut outputs = Vec::new();
+        for entry in self.entries.iter().take(index) {
+            let _ = outputs.push(entry.as_ref().clone().redo(target));
+        }
         outputs
     }
+
+    /// Moves the head to `index` without calling the edits.
+    pub(crate) fn move_to(&mut self, index: usize) {
+        if index <= self.len() && Some(index) >= self.barrier {
+            self.coalesce(|record| record.index = index);
+        }
+    }
+}
+
+impl<E: Edit, const N: usize, S: Slot, H: Evict<E, N>, K: Keyframe<E::Target>>
+    Record<E, N, S, H, K>
+{
+    /// Squashes the entries in the range into a single entry without changing the target.
+    ///
+    /// The entries are combined using [`Edit::merge`] when possible,
+    /// and the edits that can not be merged are passed to `combine`
+    /// in the order they were applied, which should return a single edit that
+    /// applies all of them in order and undoes them in reverse order.
+    /// The saved state is cleared if it was inside the range.
+    ///
+    /// Returns `false` if the range is out of bounds or the head of the record is inside the range,
+    /// in which case the record is left unchanged.
+    pub fn squash_with(
+        &mut self,
+        range: impl RangeBounds<usize>,
+        combine: impl FnMut(Vec<E, N>) -> E,
+    ) -> bool {
+        match self.squash_bounds(range) {
+            Some((start, end)) => {
+                self.squash_range(start, end, combine);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the start and end of the range if it can be squashed.
+    pub(crate) fn squash_bounds(&self, range: impl RangeBounds<usize>) -> Option<(usize, usize)> {
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start,
+            Bound::Excluded(&start) => start.checked_add(1)?,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end.checked_add(1)?,
+            Bound::Excluded(&end) => end,
+            Bound::Unbounded => self.len(),
+        };
+        if start > end || end > self.len() || (start < self.index && self.index < end) {
+            return None;
+        }
+        Some((start, end))
+    }
+
+    /// Squashes the entries in `start..end` and returns the number of entries they were squashed into.
+    pub(crate) fn squash_range(
+        &mut self,
+        start: usize,
+        end: usize,
+        combine: impl FnOnce(Vec<E, N>) -> E,
+    ) -> usize {
+        let mut entries = mem::take(&mut self.entries).into_iter();
+        for entry in entries.by_ref().take(start) {
+            let _ = self.entries.push_back(entry);
+        }
+        let mut squashed = Vec::<_, N>::new();
+        for entry in entries.by_ref().take(end - start) {
+            let size = self.size_of(&entry);
+            let last_size = squashed.last().map_or(0, |last| self.size_of(last));
+            let merged = match squashed.last_mut() {
+                Some(last) => Entry::merge(last, entry),
+                None => Merged::No(entry),
+            };
+            match merged {
+                Merged::Yes => {
+                    let last = squashed.last().expect("in the range");
+                    self.size = self.size - size - last_size + self.size_of(last);
+                }
+                Merged::Annul => {
+                    self.size -= size;
+                    let entry = squashed.pop().expect("in the range");
+                    self.evict(Evicted::Entry(entry));
+                }
+                Merged::No(entry) => {
+                    let _ = squashed.push(entry);
+                }
+            }
+        }
+        let len = squashed.len().min(1);
+        if squashed.len() > 1 {
+            let size: usize = squashed.iter().map(|entry| self.size_of(entry)).sum();
+            let entry = Entry::compound(squashed, combine);
+            self.size = self.size - size + self.size_of(&entry);
+            let _ = self.entries.push_back(entry);
+        } else if let Some(entry) = squashed.pop() {
+            let _ = self.entries.push_back(entry);
+        }
+        for entry in entries {
+            let _ = self.entries.push_back(entry);
+        }
+
+        let removed = end - start - len;
+        self.coalesce(|record| {
+            if record.index >= end {
+                record.index -= removed;
+            }
+            record.saved = match record.saved {
+                Some(saved) if saved >= end => Some(saved - removed),
+                Some(saved) if saved > start => None,
+                saved => saved,
+            };
+            // A barrier inside the range is moved to the end of the squashed entry.
+            record.barrier = record.barrier.map(|barrier| match barrier {
+                barrier if barrier >= end => barrier - removed,
+                barrier if barrier > start => start + len,
+                barrier => barrier,
+            });
+        });
+        len
+    }
+}
+
+impl<
+        E: Edit + FromIterator<E>,
+        const N: usize,
+        S: Slot,
+        H: Evict<E, N>,
+        K: Keyframe<E::Target>,
+    > Record<E, N, S, H, K>
+{
+    /// Squashes the entries in the range into a single entry without changing the target.
+    ///
+    /// The edits that can not be merged are collected into a single compound edit.
+    /// Use [`Record::squash_with`] if the edit does not implement [`FromIterator`].
+    ///
+    /// See [`Record::squash_with`] for more information.
+    pub fn squash(&mut self, range: impl RangeBounds<usize>) -> bool {
+        self.squash_with(range, |edits| edits.into_iter().collect())
+    }
 }
 
-impl<E: fmt::Display, const N: usize, S> Record<E, N, S> {
+impl<E: fmt::Display, const N: usize, S, H, K> Record<E, N, S, H, K> {
     /// Returns the string of the edit which will be undone
     /// in the next call to [`Record::undo`].
     pub fn undo_string<const SIZE: usize>(&self) -> Option<String<SIZE>> {
@@ -395,7 +1145,7 @@ impl<E: fmt::Display, const N: usize, S> Record<E, N, S> {
     }
 
     fn string_at<const SIZE: usize>(&self, i: usize) -> Option<String<SIZE>> {
-        self.entries.iter().nth(i).map(|e| {
+        self.entries.get(i).map(|e| {
             use core::fmt::Write;
             let mut result = String::<SIZE>::new();
             let _ = result.write_fmt(format_args!("{}", e));
@@ -404,6 +1154,81 @@ impl<E: fmt::Display, const N: usize, S> Record<E, N, S> {
     }
 }
 
+#[cfg(feature = "serde")]
+impl<E: Serialize, const N: usize, S, H, K> Serialize for Record<E, N, S, H, K> {
+    fn serialize<Z: Serializer>(&self, serializer: Z) -> Result<Z::Ok, Z::Error> {
+        Record::serialize(self, serializer)
+    }
+}
+
+/// Deserializes the record and checks that its invariants hold.
+///
+/// Like the slot, the settings from the builder other than the limit are not serialized.
+/// See [`Record::validate`] for more information.
+#[cfg(feature = "serde")]
+impl<'de, E: Deserialize<'de>, const N: usize, S, H, K> Deserialize<'de> for Record<E, N, S, H, K> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut record = Record::deserialize(deserializer)?;
+        record.validate().map_err(serde::de::Error::custom)?;
+        record.renew_ids();
+        Ok(record)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<E, const N: usize, S, H, K> Record<E, N, S, H, K> {
+    /// Deserializes the record and connects the slot to it.
+    ///
+    /// The slot is not serialized, so it needs to be connected again after loading.
+    pub fn deserialize_with_slot<'de, D: Deserializer<'de>>(
+        deserializer: D,
+        slot: S,
+    ) -> Result<Self, D::Error>
+    where
+        E: Deserialize<'de>,
+    {
+        let mut record = <Self as Deserialize>::deserialize(deserializer)?;
+        record.connect(slot);
+        Ok(record)
+    }
+}
+
+/// Decoding fails if there are more than `N` entries or if the invariants do not hold.
+/// The slot and the settings from the builder, except the limit, are not encoded.
+#[cfg(feature = "binary")]
+impl<E: Codec, const N: usize, S, H, K> Codec for Record<E, N, S, H, K> {
+    fn encode(&self, buf: &mut [u8]) -> Option<usize> {
+        let mut w = Writer::new(buf);
+        w.usize(self.limit())?;
+        w.usize(self.index)?;
+        w.option(self.saved)?;
+        w.option(self.barrier)?;
+        w.usize(self.len())?;
+        for entry in self.entries.iter() {
+            w.value(entry)?;
+        }
+        Some(w.len())
+    }
+
+    fn decode(bytes: &[u8]) -> Option<(Self, usize)> {
+        let mut r = Reader::new(bytes);
+        let limit = r.usize()?;
+        if limit == 0 {
+            return None;
+        }
+        let mut record = Record::builder().limit(limit).build();
+        record.index = r.usize()?;
+        record.saved = r.option()?;
+        record.barrier = r.option()?;
+        for _ in 0..r.usize()? {
+            record.entries.push_back(r.value()?).ok()?;
+        }
+        record.validate().ok()?;
+        record.renew_ids();
+        Some((record, r.len()))
+    }
+}
+
 impl<E, const N: usize> Default for Record<E, N> {
     fn default() -> Record<E, N> {
         Record::new()
@@ -1,33 +1,40 @@
-use super::Socket;
-use crate::Record;
+use super::{Budget, Socket};
+use crate::{Edit, EditSize, Evict, Keyframe, Record};
 use core::marker::PhantomData;
 use core::num::NonZeroUsize;
 use heapless::Deque;
+#[cfg(feature = "std")]
+use std::time::Duration;
 
 /// Builder for a [`Record`].
 ///
 /// # Examples
 /// ```
+/// # use heapless::String;
 /// # use undo::{Add, Record};
-/// # let mut target = String::new();
-/// let mut record = Record::builder()
+/// # let mut target = String::<256>::new();
+/// let mut record = Record::<_, 128, _>::builder()
 ///     .limit(100)
-///     .capacity(100)
 ///     .connect(|s| { dbg!(s); })
 ///     .build();
 /// # record.edit(&mut target, Add('a'));
 /// ```
 #[derive(Debug)]
-pub struct Builder<E, const N: usize, S = ()> {
+pub struct Builder<E, const N: usize, S = (), H = (), K = ()> {
     limit: NonZeroUsize,
     saved: bool,
     socket: Socket<S>,
+    evict: Option<H>,
+    keyframes: Option<K>,
+    budget: Option<Budget<E>>,
+    #[cfg(feature = "std")]
+    max_age: Option<Duration>,
     pd: PhantomData<E>,
 }
 
-impl<E, const N: usize, S> Builder<E, N, S> {
-    /// Sets the capacity for the record.
-    // pub fn capacity<const M: usize>(mut self) -> Builder<E, M, S> {
+impl<E, const N: usize, S, H, K> Builder<E, N, S, H, K> {
+    // Sets the capacity for the record.
+    // pub fn capacity<const M: usize>(mut self) -> Builder<E, M, S, H, K> {
     //     self
     // }
 
@@ -35,42 +42,120 @@ impl<E, const N: usize, S> Builder<E, N, S> {
     ///
     /// # Panics
     /// Panics if `limit` is `0`.
-    pub fn limit(mut self, limit: usize) -> Builder<E, N, S> {
+    pub fn limit(mut self, limit: usize) -> Builder<E, N, S, H, K> {
         self.limit = NonZeroUsize::new(limit).expect("limit can not be `0`");
         self
     }
 
     /// Sets if the target is initially in a saved state.
     /// By default the target is in a saved state.
-    pub fn saved(mut self, saved: bool) -> Builder<E, N, S> {
+    pub fn saved(mut self, saved: bool) -> Builder<E, N, S, H, K> {
         self.saved = saved;
         self
     }
 
     /// Connects the slot.
-    pub fn connect(mut self, slot: S) -> Builder<E, N, S> {
+    pub fn connect(mut self, slot: S) -> Builder<E, N, S, H, K> {
         self.socket = Socket::new(slot);
         self
     }
 
+    /// Sets the total size in bytes that the edits in the record can use.
+    ///
+    /// The oldest edits are removed when a new edit makes the record exceed the budget,
+    /// but the latest edit is always kept.
+    pub fn byte_budget(mut self, budget: usize) -> Builder<E, N, S, H, K>
+    where
+        E: EditSize,
+    {
+        self.budget = Some((budget, E::size));
+        self
+    }
+
+    /// Sets how long the edits are kept before they are removed.
+    ///
+    /// The age of an edit is measured from [`Entry::st_of_edit`](crate::Entry::st_of_edit),
+    /// and expired edits are removed each time a new edit is pushed
+    /// or when [`Record::enforce_retention`] is called.
+    /// Edits that are redone or moved between branches do not remove expired edits.
+    #[cfg(feature = "std")]
+    pub fn max_age(mut self, max_age: Duration) -> Builder<E, N, S, H, K> {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Sets the hook that is called with each entry that is removed from the record.
+    ///
+    /// This includes entries that are pushed out by the limit, redo tails that are discarded,
+    /// edits that annul each other and entries that are removed with methods like [`Record::clear`].
+    /// See [`Evict`] for more information.
+    pub fn on_evict<F: Evict<E, N>>(self, f: F) -> Builder<E, N, S, F, K> {
+        Builder {
+            limit: self.limit,
+            saved: self.saved,
+            socket: self.socket,
+            evict: Some(f),
+            keyframes: self.keyframes,
+            budget: self.budget,
+            #[cfg(feature = "std")]
+            max_age: self.max_age,
+            pd: PhantomData,
+        }
+    }
+
+    /// Sets the keyframes that store snapshots of the target while edits are applied.
+    ///
+    /// [`Record::go_to`] then starts from the snapshot closest to the destination
+    /// when that is fewer steps away, and only replays the remaining edits.
+    /// See [`Keyframes`](crate::Keyframes) for more information.
+    pub fn keyframes<F: Keyframe<E::Target>>(self, keyframes: F) -> Builder<E, N, S, H, F>
+    where
+        E: Edit,
+    {
+        Builder {
+            limit: self.limit,
+            saved: self.saved,
+            socket: self.socket,
+            evict: self.evict,
+            keyframes: Some(keyframes),
+            budget: self.budget,
+            #[cfg(feature = "std")]
+            max_age: self.max_age,
+            pd: PhantomData,
+        }
+    }
+
     /// Builds the record.
-    pub fn build(self) -> Record<E, N, S> {
+    pub fn build(self) -> Record<E, N, S, H, K> {
         Record {
             limit: self.limit,
             index: 0,
             saved: self.saved.then_some(0),
+            barrier: None,
             socket: self.socket,
             entries: Deque::<_, N>::new(),
+            evict: self.evict,
+            keyframes: self.keyframes,
+            next_id: 0,
+            budget: self.budget,
+            size: 0,
+            #[cfg(feature = "std")]
+            max_age: self.max_age,
         }
     }
 }
 
-impl<E, const N: usize, S> Default for Builder<E, N, S> {
+impl<E, const N: usize, S, H, K> Default for Builder<E, N, S, H, K> {
     fn default() -> Self {
         Builder {
             limit: NonZeroUsize::new(usize::MAX).unwrap(),
             saved: true,
             socket: Socket::default(),
+            evict: None,
+            keyframes: None,
+            budget: None,
+            #[cfg(feature = "std")]
+            max_age: None,
             pd: PhantomData,
         }
     }
@@ -1,4 +1,5 @@
-use crate::{Edit, Entry, Record, Slot};
+use crate::{Edit, Entry, Evict, Keyframe, Outcome, Record, Slot};
+use core::mem;
 use heapless::Deque;
 use heapless::Vec;
 
@@ -10,89 +11,523 @@ enum CheckpointEntry<E, const N: usize> {
     },
     Undo,
     Redo,
+    GoTo(usize),
+    Saved(Option<usize>),
+    Clear {
+        index: usize,
+        saved: Option<usize>,
+        barrier: Option<usize>,
+        entries: Deque<Entry<E>, N>,
+    },
+}
+
+/// A position in a checkpoint that can be rolled back to.
+///
+/// See [`Checkpoint::savepoint`] for more information.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Savepoint {
+    depth: usize,
+    len: usize,
+    generation: usize,
+}
+
+impl Savepoint {
+    pub(crate) const fn new(depth: usize, len: usize, generation: usize) -> Savepoint {
+        Savepoint {
+            depth,
+            len,
+            generation,
+        }
+    }
+
+    pub(crate) const fn depth(self) -> usize {
+        self.depth
+    }
+
+    pub(crate) const fn len(self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the savepoint has not been released or rolled past.
+    ///
+    /// The generation makes sure that a released savepoint does not become active
+    /// again when a new savepoint is created at the same depth and length.
+    pub(crate) fn is_active(self, savepoints: &[Savepoint]) -> bool {
+        savepoints.get(self.depth) == Some(&self)
+    }
 }
 
 /// Wraps a [`Record`] and gives it checkpoint functionality.
+///
+/// # Examples
+/// ```
+/// # use heapless::String;
+/// # use undo::{Add, Record};
+/// let mut target = String::<256>::new();
+/// let mut record = Record::<_, 32>::new();
+/// let mut checkpoint = record.checkpoint::<16>();
+///
+/// checkpoint.edit(&mut target, Add('a'));
+/// let savepoint = checkpoint.savepoint();
+/// checkpoint.edit(&mut target, Add('b'));
+/// checkpoint.edit(&mut target, Add('c'));
+/// assert_eq!(target, "abc");
+///
+/// // Only the edits made after the savepoint are undone.
+/// checkpoint.rollback_to(&mut target, savepoint);
+/// assert_eq!(target, "a");
+///
+/// checkpoint.cancel(&mut target);
+/// assert_eq!(target, "");
+/// ```
 #[derive(Debug)]
-pub struct Checkpoint<'a, E, const N: usize, const M: usize, S> {
-    record: &'a mut Record<E, N, S>,
+pub struct Checkpoint<'a, E, const N: usize, const M: usize, S, H, K> {
+    record: &'a mut Record<E, N, S, H, K>,
     entries: Vec<CheckpointEntry<E, N>, M>,
+    savepoints: Vec<Savepoint, M>,
+    generation: usize,
 }
 
-impl<E, const N: usize, const M: usize, S> Checkpoint<'_, E, N, M, S> {
-    /// Reserves capacity for at least `additional` more entries in the checkpoint.
-    ///
-    /// # Panics
-    /// Panics if the new capacity exceeds `isize::MAX` bytes.
+impl<E, const N: usize, const M: usize, S, H, K> Checkpoint<'_, E, N, M, S, H, K> {
+    // Reserves capacity for at least `additional` more entries in the checkpoint.
+    //
+    // # Panics
+    // Panics if the new capacity exceeds `isize::MAX` bytes.
     // pub fn reserve(&mut self, additional: usize) {
     //     self.entries.reserve(additional);
     // }
 
+    /// Creates a savepoint at the current position in the checkpoint.
+    ///
+    /// Savepoints can be nested, and rolling back to a savepoint
+    /// only undoes the changes made after it was created.
+    pub fn savepoint(&mut self) -> Savepoint {
+        let savepoint = Savepoint::new(self.savepoints.len(), self.entries.len(), self.generation);
+        self.generation += 1;
+        if self.savepoints.push(savepoint).is_err() {
+            panic!("Entry limit exceeded!!")
+        }
+        savepoint
+    }
+
+    /// Releases the savepoint and all savepoints created after it.
+    ///
+    /// The changes made after the savepoint are kept as part of the checkpoint.
+    pub fn release(&mut self, savepoint: Savepoint) {
+        if savepoint.is_active(&self.savepoints) {
+            self.savepoints.truncate(savepoint.depth);
+        }
+    }
+}
+
+impl<E, const N: usize, const M: usize, S, H: Evict<E, N>, K> Checkpoint<'_, E, N, M, S, H, K> {
     /// Commits the changes and consumes the checkpoint.
-    pub fn commit(self) {}
+    pub fn commit(mut self) {
+        self.evict();
+    }
+
+    /// Passes the entries that were discarded by the changes to the eviction hook.
+    pub(crate) fn evict(&mut self) {
+        for entry in mem::take(&mut self.entries) {
+            match entry {
+                CheckpointEntry::Edit { tail, .. } => self.record.evict_entries(tail),
+                CheckpointEntry::Clear { entries, .. } => self.record.evict_entries(entries),
+                _ => (),
+            }
+        }
+    }
 }
 
-impl<E: Edit, const N: usize, const M: usize, S: Slot> Checkpoint<'_, E, N, M, S> {
+impl<E: Edit, const N: usize, const M: usize, S: Slot, H: Evict<E, N>, K: Keyframe<E::Target>>
+    Checkpoint<'_, E, N, M, S, H, K>
+{
     /// Calls the `apply` method.
+    ///
+    /// The edit is not merged with the previous edit, so it can be cancelled on its own.
     pub fn edit(&mut self, target: &mut E::Target, edit: E) -> E::Output {
-        let (output, _, tail, saved) = self.record.edit_and_push(target, Entry::new(edit));
-        if self
-            .entries
-            .push(CheckpointEntry::Edit { saved, tail })
-            .is_err()
-        {
-            panic!("Entry limit exceeded!!")
-        }
+        let mut entry = self.record.new_entry(edit);
+        let output = entry.edit(target);
+        self.push_edit(entry);
         output
     }
 
     /// Calls the `undo` method.
     pub fn undo(&mut self, target: &mut E::Target) -> Option<E::Output> {
         let output = self.record.undo(target)?;
-        if self.entries.push(CheckpointEntry::Undo).is_err() {
-            panic!("Entry limit exceeded!!")
-        }
+        self.push(CheckpointEntry::Undo);
         Some(output)
     }
 
     /// Calls the `redo` method.
     pub fn redo(&mut self, target: &mut E::Target) -> Option<E::Output> {
         let output = self.record.redo(target)?;
-        if self.entries.push(CheckpointEntry::Redo).is_err() {
-            panic!("Entry limit exceeded!!")
-        }
+        self.push(CheckpointEntry::Redo);
         Some(output)
     }
 
+    /// Calls the [`Record::go_to`] method.
+    pub fn go_to(&mut self, target: &mut E::Target, index: usize) -> Vec<E::Output, N> {
+        let old = self.record.head();
+        let outputs = self.record.go_to(target, index);
+        self.push(CheckpointEntry::GoTo(old));
+        outputs
+    }
+
+    /// Calls the [`Record::revert`] method.
+    pub fn revert(&mut self, target: &mut E::Target) -> Vec<E::Output, N> {
+        let old = self.record.head();
+        let outputs = self.record.revert(target);
+        self.push(CheckpointEntry::GoTo(old));
+        outputs
+    }
+
+    /// Calls the [`Record::set_saved`] method.
+    pub fn set_saved(&mut self) {
+        self.push(CheckpointEntry::Saved(self.record.saved));
+        self.record.set_saved();
+    }
+
+    /// Calls the [`Record::clear_saved`] method.
+    pub fn clear_saved(&mut self) {
+        self.push(CheckpointEntry::Saved(self.record.saved));
+        self.record.clear_saved();
+    }
+
+    /// Calls the [`Record::clear`] method.
+    pub fn clear(&mut self) {
+        let (entries, index, saved, barrier) = self.record.rm_entries();
+        self.push(CheckpointEntry::Clear {
+            index,
+            saved,
+            barrier,
+            entries,
+        });
+    }
+
+    /// Cancels the changes made after the savepoint.
+    ///
+    /// The savepoint is kept so it can be rolled back to again,
+    /// but all savepoints created after it are released.
+    /// Does nothing if the savepoint has already been released.
+    pub fn rollback_to(
+        &mut self,
+        target: &mut E::Target,
+        savepoint: Savepoint,
+    ) -> Vec<E::Output, M> {
+        if !savepoint.is_active(&self.savepoints) {
+            return Vec::new();
+        }
+        self.savepoints.truncate(savepoint.depth + 1);
+        self.undo_to(target, savepoint.len)
+    }
+
     /// Cancels the changes and consumes the checkpoint.
     pub fn cancel(mut self, target: &mut E::Target) -> Vec<E::Output, M> {
-        self.entries.as_mut_slice().reverse();
-        self.entries
-            .into_iter()
-            .filter_map(|entry| match entry {
-                CheckpointEntry::Edit { saved, tail } => {
-                    let output = self.record.undo(target)?;
-                    self.record.entries.pop_back();
-                    for en in tail {
-                        let _ = self.record.entries.push_front(en);
-                    }
-                    self.record.saved = self.record.saved.or(saved);
-                    Some(output)
+        self.undo_to(target, 0)
+    }
+
+    fn undo_to(&mut self, target: &mut E::Target, len: usize) -> Vec<E::Output, M> {
+        let mut outputs = Vec::new();
+        while self.entries.len() > len {
+            let entry = self.entries.pop().expect("in the range");
+            self.undo_entry(target, entry, &mut outputs);
+        }
+        outputs
+    }
+
+    fn undo_entry(
+        &mut self,
+        target: &mut E::Target,
+        entry: CheckpointEntry<E, N>,
+        outputs: &mut Vec<E::Output, M>,
+    ) {
+        let output = match entry {
+            CheckpointEntry::Edit { saved, tail } => {
+                let output = self.record.undo(target);
+                if output.is_some() {
+                    self.restore(saved, tail);
                 }
-                CheckpointEntry::Undo => self.record.redo(target),
-                CheckpointEntry::Redo => self.record.undo(target),
-            })
-            .collect()
+                output
+            }
+            CheckpointEntry::Undo => self.record.redo(target),
+            CheckpointEntry::Redo => self.record.undo(target),
+            CheckpointEntry::GoTo(index) => {
+                for output in self.record.go_to(target, index) {
+                    let _ = outputs.push(output);
+                }
+                None
+            }
+            CheckpointEntry::Saved(saved) => {
+                self.record.coalesce(|record| record.saved = saved);
+                None
+            }
+            CheckpointEntry::Clear {
+                index,
+                saved,
+                barrier,
+                entries,
+            } => {
+                self.record.coalesce(|record| {
+                    record.entries = entries;
+                    record.index = index;
+                    record.saved = saved;
+                    record.barrier = barrier;
+                });
+                None
+            }
+        };
+        if let Some(output) = output {
+            let _ = outputs.push(output);
+        }
+    }
+
+    /// Removes the edit above the head and puts back the entries it replaced.
+    fn restore(&mut self, saved: Option<usize>, tail: Deque<Entry<E>, N>) {
+        let entry = self.record.entries.pop_back().expect("in the range");
+        self.record.discard(entry);
+        for en in tail {
+            let _ = self.record.entries.push_back(en);
+        }
+        self.record.saved = self.record.saved.or(saved);
+    }
+
+    /// Pushes the applied edit on top of the record.
+    fn push_edit(&mut self, entry: Entry<E>) {
+        let (_, tail, saved) = self.record.push(entry, false);
+        self.record.expire();
+        self.push(CheckpointEntry::Edit { saved, tail });
+    }
+
+    fn push(&mut self, entry: CheckpointEntry<E, N>) {
+        if self.entries.push(entry).is_err() {
+            panic!("Entry limit exceeded!!")
+        }
+    }
+}
+
+impl<E, T, Er, const N: usize, const M: usize, S, H, K> Checkpoint<'_, E, N, M, S, H, K>
+where
+    E: Edit,
+    E::Output: Outcome<Ok = T, Error = Er>,
+    S: Slot,
+    H: Evict<E, N>,
+    K: Keyframe<E::Target>,
+{
+    /// Calls [`Checkpoint::edit`], but does not push the edit if it fails.
+    pub(crate) fn try_edit(&mut self, target: &mut E::Target, edit: E) -> Result<T, Er> {
+        let mut entry = self.record.new_entry(edit);
+        let output = entry.edit(target).into_result()?;
+        self.push_edit(entry);
+        Ok(output)
+    }
+
+    /// Calls [`Checkpoint::undo`], but moves the index back if the undo fails.
+    pub(crate) fn try_undo(&mut self, target: &mut E::Target) -> Result<Option<T>, Er> {
+        let Some(output) = self.record.undo(target) else {
+            return Ok(None);
+        };
+        match output.into_result() {
+            Ok(output) => {
+                self.push(CheckpointEntry::Undo);
+                Ok(Some(output))
+            }
+            Err(err) => {
+                // The failed undo did not change the target, so only the index is restored.
+                let index = self.record.head() + 1;
+                self.record.coalesce(|record| record.index = index);
+                Err(err)
+            }
+        }
+    }
+
+    /// Calls [`Checkpoint::redo`], but moves the index back if the redo fails.
+    pub(crate) fn try_redo(&mut self, target: &mut E::Target) -> Result<Option<T>, Er> {
+        let Some(output) = self.record.redo(target) else {
+            return Ok(None);
+        };
+        match output.into_result() {
+            Ok(output) => {
+                self.push(CheckpointEntry::Redo);
+                Ok(Some(output))
+            }
+            Err(err) => {
+                let index = self.record.head() - 1;
+                self.record.coalesce(|record| record.index = index);
+                Err(err)
+            }
+        }
+    }
+
+    /// Undoes or redoes the edits until `index` is reached, stopping at the first step that fails.
+    ///
+    /// Only one entry is pushed to the checkpoint, no matter how many steps are taken.
+    pub(crate) fn try_go_to(
+        &mut self,
+        target: &mut E::Target,
+        index: usize,
+    ) -> Result<Vec<T, N>, Er> {
+        let mut outputs = Vec::new();
+        if index > self.record.len() || Some(index) < self.record.barrier {
+            return Ok(outputs);
+        }
+        self.push(CheckpointEntry::GoTo(self.record.head()));
+        while self.record.head() != index {
+            let redo = index > self.record.head();
+            let output = if redo {
+                self.record.redo(target)
+            } else {
+                self.record.undo(target)
+            };
+            match output.expect("in the range").into_result() {
+                Ok(output) => {
+                    let _ = outputs.push(output);
+                }
+                Err(err) => {
+                    // The failed step did not change the target, so only the index is restored.
+                    let head = self.record.head();
+                    let index = if redo { head - 1 } else { head + 1 };
+                    self.record.coalesce(|record| record.index = index);
+                    return Err(err);
+                }
+            }
+        }
+        Ok(outputs)
+    }
+
+    /// Calls [`Checkpoint::try_go_to`] with the saved state.
+    pub(crate) fn try_revert(&mut self, target: &mut E::Target) -> Result<Vec<T, N>, Er> {
+        match self.record.saved {
+            Some(saved) => self.try_go_to(target, saved),
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+/// A checkpoint that cancels the changes when dropped unless it is committed.
+///
+/// The guard borrows both the record and the target, which makes sure that
+/// the changes are rolled back if the code returns early, e.g. by using `?`.
+///
+/// # Examples
+/// ```
+/// # use heapless::String;
+/// # use undo::{Add, Record};
+/// let mut target = String::<256>::new();
+/// let mut record = Record::<_, 32>::new();
+///
+/// let mut guard = record.guard::<16>(&mut target);
+/// guard.edit(Add('a'));
+/// guard.edit(Add('b'));
+/// assert_eq!(guard.target(), "ab");
+/// drop(guard);
+/// assert_eq!(target, "");
+///
+/// let mut guard = record.guard::<16>(&mut target);
+/// guard.edit(Add('c'));
+/// guard.commit();
+/// assert_eq!(target, "c");
+/// ```
+#[derive(Debug)]
+pub struct Guard<
+    'a,
+    E: Edit,
+    const N: usize,
+    const M: usize,
+    S: Slot,
+    H: Evict<E, N>,
+    K: Keyframe<E::Target>,
+> {
+    checkpoint: Checkpoint<'a, E, N, M, S, H, K>,
+    target: &'a mut E::Target,
+    committed: bool,
+}
+
+impl<
+        'a,
+        E: Edit,
+        const N: usize,
+        const M: usize,
+        S: Slot,
+        H: Evict<E, N>,
+        K: Keyframe<E::Target>,
+    > Guard<'a, E, N, M, S, H, K>
+{
+    pub(crate) fn new(record: &'a mut Record<E, N, S, H, K>, target: &'a mut E::Target) -> Self {
+        Guard {
+            checkpoint: Checkpoint::from(record),
+            target,
+            committed: false,
+        }
+    }
+
+    /// Returns a reference to the target.
+    pub fn target(&self) -> &E::Target {
+        self.target
+    }
+
+    /// Calls the [`Checkpoint::edit`] method.
+    pub fn edit(&mut self, edit: E) -> E::Output {
+        self.checkpoint.edit(self.target, edit)
+    }
+
+    /// Calls the [`Checkpoint::undo`] method.
+    pub fn undo(&mut self) -> Option<E::Output> {
+        self.checkpoint.undo(self.target)
+    }
+
+    /// Calls the [`Checkpoint::redo`] method.
+    pub fn redo(&mut self) -> Option<E::Output> {
+        self.checkpoint.redo(self.target)
+    }
+
+    /// Calls the [`Checkpoint::savepoint`] method.
+    pub fn savepoint(&mut self) -> Savepoint {
+        self.checkpoint.savepoint()
+    }
+
+    /// Calls the [`Checkpoint::release`] method.
+    pub fn release(&mut self, savepoint: Savepoint) {
+        self.checkpoint.release(savepoint);
+    }
+
+    /// Calls the [`Checkpoint::rollback_to`] method.
+    pub fn rollback_to(&mut self, savepoint: Savepoint) -> Vec<E::Output, M> {
+        self.checkpoint.rollback_to(self.target, savepoint)
+    }
+
+    /// Commits the changes and consumes the guard.
+    pub fn commit(mut self) {
+        self.committed = true;
+    }
+
+    /// Cancels the changes and consumes the guard.
+    pub fn cancel(mut self) -> Vec<E::Output, M> {
+        self.committed = true;
+        self.checkpoint.undo_to(self.target, 0)
+    }
+}
+
+impl<E: Edit, const N: usize, const M: usize, S: Slot, H: Evict<E, N>, K: Keyframe<E::Target>> Drop
+    for Guard<'_, E, N, M, S, H, K>
+{
+    fn drop(&mut self) {
+        if self.committed {
+            self.checkpoint.evict();
+        } else {
+            self.checkpoint.undo_to(self.target, 0);
+        }
     }
 }
 
-impl<'a, E, const N: usize, const M: usize, S> From<&'a mut Record<E, N, S>>
-    for Checkpoint<'a, E, N, M, S>
+impl<'a, E, const N: usize, const M: usize, S, H, K> From<&'a mut Record<E, N, S, H, K>>
+    for Checkpoint<'a, E, N, M, S, H, K>
 {
-    fn from(record: &'a mut Record<E, N, S>) -> Self {
+    fn from(record: &'a mut Record<E, N, S, H, K>) -> Self {
         Checkpoint {
             record,
             entries: Vec::new(),
+            savepoints: Vec::new(),
+            generation: 0,
         }
     }
 }
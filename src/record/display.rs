@@ -1,17 +1,18 @@
 use crate::{At, Entry, Format, Record};
 use core::fmt::{self, Write};
+#[cfg(feature = "std")]
 use heapless::String;
 #[cfg(feature = "std")]
 use std::time::SystemTime;
 /// Configurable display formatting for the [`Record`].
-pub struct Display<'a, E, const N: usize, const SIZE: usize, S> {
-    record: &'a Record<E, N, S>,
+pub struct Display<'a, E, const N: usize, const SIZE: usize, S, H, K> {
+    record: &'a Record<E, N, S, H, K>,
     format: Format,
     #[cfg(feature = "std")]
     st_fmt: &'a dyn Fn(SystemTime, SystemTime) -> String<SIZE>,
 }
 
-impl<'a, E, const N: usize, const SIZE: usize, S> Display<'a, E, N, SIZE, S> {
+impl<'a, E, const N: usize, const SIZE: usize, S, H, K> Display<'a, E, N, SIZE, S, H, K> {
     /// Show colored output (on by default).
     ///
     /// Requires the `colored` feature to be enabled.
@@ -39,6 +40,18 @@ impl<'a, E, const N: usize, const SIZE: usize, S> Display<'a, E, N, SIZE, S> {
         self
     }
 
+    /// Show the barrier (on by default).
+    pub fn barrier(&mut self, on: bool) -> &mut Self {
+        self.format.barrier = on;
+        self
+    }
+
+    /// Show the pinned edits (on by default).
+    pub fn pinned(&mut self, on: bool) -> &mut Self {
+        self.format.pinned = on;
+        self
+    }
+
     /// Sets the format used to display [`SystemTime`]s.
     ///
     /// The first input parameter is the current system time.
@@ -53,7 +66,7 @@ impl<'a, E, const N: usize, const SIZE: usize, S> Display<'a, E, N, SIZE, S> {
     }
 }
 
-impl<E: fmt::Display, const N: usize, const SIZE: usize, S> Display<'_, E, N, SIZE, S> {
+impl<E: fmt::Display, const N: usize, const SIZE: usize, S, H, K> Display<'_, E, N, SIZE, S, H, K> {
     fn fmt_list(
         &self,
         f: &mut fmt::Formatter,
@@ -77,6 +90,8 @@ impl<E: fmt::Display, const N: usize, const SIZE: usize, S> Display<'_, E, N, SI
             At::no_root(index),
             At::no_root(self.record.index),
             self.record.saved.map(At::no_root),
+            self.record.barrier.map(At::no_root),
+            entry.is_some_and(Entry::is_pinned),
         )?;
 
         if let Some(entry) = entry {
@@ -93,10 +108,10 @@ impl<E: fmt::Display, const N: usize, const SIZE: usize, S> Display<'_, E, N, SI
     }
 }
 
-impl<'a, E, const N: usize, const SIZE: usize, S> From<&'a Record<E, N, S>>
-    for Display<'a, E, N, SIZE, S>
+impl<'a, E, const N: usize, const SIZE: usize, S, H, K> From<&'a Record<E, N, S, H, K>>
+    for Display<'a, E, N, SIZE, S, H, K>
 {
-    fn from(record: &'a Record<E, N, S>) -> Self {
+    fn from(record: &'a Record<E, N, S, H, K>) -> Self {
         Display {
             record,
             format: Format::default(),
@@ -106,8 +121,8 @@ impl<'a, E, const N: usize, const SIZE: usize, S> From<&'a Record<E, N, S>>
     }
 }
 
-impl<E: fmt::Display, const N: usize, const SIZE: usize, S> fmt::Display
-    for Display<'_, E, N, SIZE, S>
+impl<E: fmt::Display, const N: usize, const SIZE: usize, S, H, K> fmt::Display
+    for Display<'_, E, N, SIZE, S, H, K>
 {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         #[cfg(feature = "std")]
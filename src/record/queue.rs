@@ -1,4 +1,5 @@
-use crate::{Edit, Record, Slot};
+use crate::record::Checkpoint;
+use crate::{Edit, Evict, Keyframe, Merged, Outcome, Record, Slot};
 use heapless::Vec;
 
 #[derive(Debug)]
@@ -6,16 +7,45 @@ enum QueueEntry<E> {
     Edit(E),
     Undo,
     Redo,
+    GoTo(usize),
+    Revert,
+    SetSaved,
+    ClearSaved,
+    Clear,
+}
+
+/// The output of an operation applied by a queue.
+///
+/// The queue returns one output for each queued operation, in the order they were queued.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum QueueOutput<O, const N: usize> {
+    /// The output of an edit.
+    Edit(O),
+    /// The output of an undo, or `None` if there was nothing to undo.
+    Undo(Option<O>),
+    /// The output of a redo, or `None` if there was nothing to redo.
+    Redo(Option<O>),
+    /// The outputs of a go to.
+    GoTo(Vec<O, N>),
+    /// The outputs of a revert.
+    Revert(Vec<O, N>),
+    /// The saved state was set.
+    SetSaved,
+    /// The saved state was cleared.
+    ClearSaved,
+    /// The edits were cleared.
+    Clear,
 }
 
 /// Wraps a [`Record`] and gives it batch queue functionality.
 ///
 /// # Examples
 /// ```
+/// # use heapless::String;
 /// # use undo::{Add, Record};
-/// let mut string = String::new();
-/// let mut record = Record::new();
-/// let mut queue = record.queue();
+/// let mut string = String::<256>::new();
+/// let mut record = Record::<_, 32>::new();
+/// let mut queue = record.queue::<16>();
 ///
 /// queue.edit(Add('a'));
 /// queue.edit(Add('b'));
@@ -26,66 +56,219 @@ enum QueueEntry<E> {
 /// assert_eq!(string, "abc");
 /// ```
 #[derive(Debug)]
-pub struct Queue<'a, E, const N: usize, const M: usize, S> {
-    record: &'a mut Record<E, N, S>,
+pub struct Queue<'a, E, const N: usize, const M: usize, S, H, K> {
+    record: &'a mut Record<E, N, S, H, K>,
     entries: Vec<QueueEntry<E>, M>,
+    premerge: bool,
 }
 
-impl<E, const N: usize, const M: usize, S> Queue<'_, E, N, M, S> {
-    /// Reserves capacity for at least `additional` more entries in the queue.
-    ///
-    /// # Panics
-    /// Panics if the new capacity exceeds `isize::MAX` bytes.
+impl<E, const N: usize, const M: usize, S, H, K> Queue<'_, E, N, M, S, H, K> {
+    // Reserves capacity for at least `additional` more entries in the queue.
+    //
+    // # Panics
+    // Panics if the new capacity exceeds `isize::MAX` bytes.
     // pub fn reserve(&mut self, additional: usize) {
     //     self.entries.reserve(additional);
     // }
 
-    /// Queues a [`Record::edit`] call.
-    pub fn edit(&mut self, edit: E) {
-        if self.entries.push(QueueEntry::Edit(edit)).is_err() {
-            panic!("Entry limit exceeded!!")
-        }
+    /// Merge the queued edits using [`Edit::merge`] before they are applied (off by default).
+    ///
+    /// When on, an edit that merges with the previously queued edit does not
+    /// use a new slot in the queue, and the merged edits are applied as a single edit.
+    pub fn premerge(&mut self, on: bool) -> &mut Self {
+        self.premerge = on;
+        self
     }
 
     /// Queues a [`Record::undo`] call.
     pub fn undo(&mut self) {
-        if self.entries.push(QueueEntry::Undo).is_err() {
-            panic!("Entry limit exceeded!!")
-        }
+        self.push(QueueEntry::Undo);
     }
 
     /// Queues a [`Record::redo`] call.
     pub fn redo(&mut self) {
-        if self.entries.push(QueueEntry::Redo).is_err() {
-            panic!("Entry limit exceeded!!")
-        }
+        self.push(QueueEntry::Redo);
+    }
+
+    /// Queues a [`Record::go_to`] call.
+    pub fn go_to(&mut self, index: usize) {
+        self.push(QueueEntry::GoTo(index));
+    }
+
+    /// Queues a [`Record::revert`] call.
+    pub fn revert(&mut self) {
+        self.push(QueueEntry::Revert);
+    }
+
+    /// Queues a [`Record::set_saved`] call.
+    pub fn set_saved(&mut self) {
+        self.push(QueueEntry::SetSaved);
+    }
+
+    /// Queues a [`Record::clear_saved`] call.
+    pub fn clear_saved(&mut self) {
+        self.push(QueueEntry::ClearSaved);
+    }
+
+    /// Queues a [`Record::clear`] call.
+    pub fn clear(&mut self) {
+        self.push(QueueEntry::Clear);
     }
 
     /// Cancels the queued edits.
     pub fn cancel(self) {}
+
+    fn push(&mut self, entry: QueueEntry<E>) {
+        if self.entries.push(entry).is_err() {
+            panic!("Entry limit exceeded!!")
+        }
+    }
 }
 
-impl<E: Edit, const N: usize, const M: usize, S: Slot> Queue<'_, E, N, M, S> {
-    /// Applies the queued edits.
-    pub fn commit(self, target: &mut E::Target) -> Vec<E::Output, M> {
+impl<E: Edit, const N: usize, const M: usize, S, H, K> Queue<'_, E, N, M, S, H, K> {
+    /// Queues a [`Record::edit`] call.
+    pub fn edit(&mut self, edit: E) {
+        let edit = match self.entries.last_mut() {
+            Some(QueueEntry::Edit(last)) if self.premerge => match last.merge(edit) {
+                Merged::Yes => return,
+                Merged::Annul => {
+                    self.entries.pop();
+                    return;
+                }
+                Merged::No(edit) => edit,
+            },
+            _ => edit,
+        };
+        self.push(QueueEntry::Edit(edit));
+    }
+}
+
+impl<E: Edit, const N: usize, const M: usize, S: Slot, H: Evict<E, N>, K: Keyframe<E::Target>>
+    Queue<'_, E, N, M, S, H, K>
+{
+    /// Applies the queued operations in the order they were queued.
+    pub fn commit(self, target: &mut E::Target) -> Vec<QueueOutput<E::Output, N>, M> {
         self.entries
             .into_iter()
-            .filter_map(|entry| match entry {
-                QueueEntry::Edit(edit) => Some(self.record.edit(target, edit)),
-                QueueEntry::Undo => self.record.undo(target),
-                QueueEntry::Redo => self.record.redo(target),
+            .map(|entry| match entry {
+                QueueEntry::Edit(edit) => QueueOutput::Edit(self.record.edit(target, edit)),
+                QueueEntry::Undo => QueueOutput::Undo(self.record.undo(target)),
+                QueueEntry::Redo => QueueOutput::Redo(self.record.redo(target)),
+                QueueEntry::GoTo(index) => QueueOutput::GoTo(self.record.go_to(target, index)),
+                QueueEntry::Revert => QueueOutput::Revert(self.record.revert(target)),
+                QueueEntry::SetSaved => {
+                    self.record.set_saved();
+                    QueueOutput::SetSaved
+                }
+                QueueEntry::ClearSaved => {
+                    self.record.clear_saved();
+                    QueueOutput::ClearSaved
+                }
+                QueueEntry::Clear => {
+                    self.record.clear();
+                    QueueOutput::Clear
+                }
             })
             .collect()
     }
 }
 
-impl<'a, E, const N: usize, const M: usize, S> From<&'a mut Record<E, N, S>>
-    for Queue<'a, E, N, M, S>
+impl<E, T, Er, const N: usize, const M: usize, S, H, K> Queue<'_, E, N, M, S, H, K>
+where
+    E: Edit,
+    E::Output: Outcome<Ok = T, Error = Er>,
+    S: Slot,
+    H: Evict<E, N>,
+    K: Keyframe<E::Target>,
+{
+    /// Applies the queued operations in the order they were queued,
+    /// or none of them if any of the operations fails.
+    ///
+    /// An edit, undo or redo that fails is assumed to not have changed the target,
+    /// and is removed without calling the opposite method. All the operations that
+    /// were applied before it are reverted, which leaves the record in the same state
+    /// as before the commit, and the error is returned. A go to or revert stops at the
+    /// first step that fails.
+    ///
+    /// The edits are applied like in a [`Checkpoint`], so they are not merged with the edits
+    /// in the record. Use [`Queue::premerge`] to merge the queued edits.
+    ///
+    /// # Examples
+    /// ```
+    /// # use undo::{Edit, Record};
+    /// struct Push(u8);
+    ///
+    /// impl Edit for Push {
+    ///     type Target = heapless::Vec<u8, 2>;
+    ///     type Output = Result<(), u8>;
+    ///
+    ///     fn edit(&mut self, target: &mut Self::Target) -> Result<(), u8> {
+    ///         target.push(self.0)
+    ///     }
+    ///
+    ///     fn undo(&mut self, target: &mut Self::Target) -> Result<(), u8> {
+    ///         target.pop().map(drop).ok_or(self.0)
+    ///     }
+    /// }
+    ///
+    /// let mut target = heapless::Vec::new();
+    /// let mut record = Record::<_, 32>::new();
+    /// let mut queue = record.queue::<16>();
+    /// queue.edit(Push(1));
+    /// queue.edit(Push(2));
+    /// queue.edit(Push(3));
+    /// assert_eq!(queue.try_commit(&mut target), Err(3));
+    /// assert!(target.is_empty());
+    /// assert!(record.is_empty());
+    /// ```
+    pub fn try_commit(self, target: &mut E::Target) -> Result<Vec<QueueOutput<T, N>, M>, Er> {
+        let mut checkpoint = Checkpoint::<E, N, M, S, H, K>::from(self.record);
+        let mut outputs = Vec::new();
+        for entry in self.entries {
+            let output = match entry {
+                QueueEntry::Edit(edit) => checkpoint.try_edit(target, edit).map(QueueOutput::Edit),
+                QueueEntry::Undo => checkpoint.try_undo(target).map(QueueOutput::Undo),
+                QueueEntry::Redo => checkpoint.try_redo(target).map(QueueOutput::Redo),
+                QueueEntry::GoTo(index) => {
+                    checkpoint.try_go_to(target, index).map(QueueOutput::GoTo)
+                }
+                QueueEntry::Revert => checkpoint.try_revert(target).map(QueueOutput::Revert),
+                QueueEntry::SetSaved => {
+                    checkpoint.set_saved();
+                    Ok(QueueOutput::SetSaved)
+                }
+                QueueEntry::ClearSaved => {
+                    checkpoint.clear_saved();
+                    Ok(QueueOutput::ClearSaved)
+                }
+                QueueEntry::Clear => {
+                    checkpoint.clear();
+                    Ok(QueueOutput::Clear)
+                }
+            };
+            match output {
+                Ok(output) => {
+                    let _ = outputs.push(output);
+                }
+                Err(err) => {
+                    checkpoint.cancel(target);
+                    return Err(err);
+                }
+            }
+        }
+        checkpoint.commit();
+        Ok(outputs)
+    }
+}
+
+impl<'a, E, const N: usize, const M: usize, S, H, K> From<&'a mut Record<E, N, S, H, K>>
+    for Queue<'a, E, N, M, S, H, K>
 {
-    fn from(record: &'a mut Record<E, N, S>) -> Self {
+    fn from(record: &'a mut Record<E, N, S, H, K>) -> Self {
         Queue {
             record,
             entries: Vec::new(),
+            premerge: false,
         }
     }
 }
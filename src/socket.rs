@@ -1,13 +1,10 @@
 //! Module used to communicate changes in the data structures.
 
 use core::mem;
-#[cfg(feature = "serde")]
-use serde::{Deserialize, Serialize};
 #[cfg(feature = "std")]
 use std::sync::mpsc::{Sender, SyncSender};
 
 /// Slot wrapper that adds some additional functionality.
-#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[repr(transparent)]
 #[derive(Clone, Debug)]
 pub(crate) struct Socket<S>(Option<S>);
@@ -62,6 +59,11 @@ pub enum Event {
     Root(usize),
     /// Emitted when the index has changed.
     Index(usize),
+    /// Emitted when the barrier has changed.
+    Barrier(Option<usize>),
+    /// Emitted when an edit is kept even though the record is at its limit,
+    /// because the oldest edits are pinned and can not be merged.
+    LimitExceeded,
 }
 
 /// Handles events.
@@ -69,12 +71,13 @@ pub enum Event {
 /// # Examples
 /// ```
 /// # use std::sync::mpsc;
+/// # use heapless::String;
 /// # use undo::{Add, Record, Event};
 /// let (sender, receiver) = mpsc::channel();
 /// let mut iter = receiver.try_iter();
 ///
-/// let mut target = String::new();
-/// let mut record = Record::builder()
+/// let mut target = String::<256>::new();
+/// let mut record = Record::<_, 32, _>::builder()
 ///     .connect(sender)
 ///     .build();
 ///
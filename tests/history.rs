@@ -1,5 +1,6 @@
 use heapless::String;
-use undo::{Add, At, History};
+use undo::record::QueueOutput;
+use undo::{Add, At, Event, History, Keyframes};
 
 const A: Add<256> = Add('a');
 const B: Add<256> = Add('b');
@@ -123,6 +124,48 @@ fn checkpoint() {
     assert_eq!(target, "");
 }
 
+#[test]
+fn queue() {
+    let mut target = String::<256>::new();
+    let mut history = History::<_, 32>::new();
+    history.edit(&mut target, A);
+    let root = history.head().root;
+
+    let mut queue = history.queue::<16>();
+    queue.edit(B);
+    queue.set_saved();
+    queue.edit(C);
+    queue.go_to(At::new(root, 0));
+    queue.redo();
+    queue.revert();
+    queue.clear_saved();
+    let outputs = queue.commit(&mut target);
+    assert_eq!(target, "ab");
+    assert_eq!(
+        &outputs[..],
+        &[
+            QueueOutput::Edit(()),
+            QueueOutput::SetSaved,
+            QueueOutput::Edit(()),
+            QueueOutput::GoTo([(), (), ()].into_iter().collect()),
+            QueueOutput::Redo(Some(())),
+            QueueOutput::Revert([()].into_iter().collect()),
+            QueueOutput::ClearSaved,
+        ][..]
+    );
+    assert!(!history.is_saved());
+
+    let mut queue = history.queue::<16>();
+    queue.clear();
+    queue.undo();
+    let outputs = queue.commit(&mut target);
+    assert_eq!(
+        &outputs[..],
+        &[QueueOutput::Clear, QueueOutput::Undo(None)][..]
+    );
+    assert!(history.is_empty());
+}
+
 #[test]
 fn next_and_prev() {
     let mut target = String::new();
@@ -145,3 +188,1145 @@ fn next_and_prev() {
     assert_eq!(history.next_branch_head(), Some(At::new(1, 2)));
     assert_eq!(history.prev_branch_head(), None);
 }
+
+#[test]
+fn checkpoint_savepoint() {
+    let mut target = String::<256>::new();
+    let mut history = History::<_, 32>::new();
+    history.edit(&mut target, A);
+    history.edit(&mut target, B);
+    history.undo(&mut target).unwrap();
+
+    let mut checkpoint = history.checkpoint::<16>();
+    checkpoint.edit(&mut target, C);
+    let savepoint = checkpoint.savepoint();
+    checkpoint.undo(&mut target);
+    checkpoint.edit(&mut target, D);
+    checkpoint.edit(&mut target, E);
+    assert_eq!(target, "ade");
+
+    checkpoint.rollback_to(&mut target, savepoint);
+    assert_eq!(target, "ac");
+    checkpoint.cancel(&mut target);
+    assert_eq!(target, "a");
+    assert_eq!(history.branches().count(), 1);
+
+    history.redo(&mut target).unwrap();
+    assert_eq!(target, "ab");
+}
+
+#[test]
+fn guard() {
+    let mut target = String::<256>::new();
+    let mut history = History::<_, 32>::new();
+    history.edit(&mut target, A);
+    history.edit(&mut target, B);
+    history.undo(&mut target).unwrap();
+
+    let mut guard = history.guard::<16>(&mut target);
+    guard.edit(C);
+    guard.edit(D);
+    assert_eq!(guard.target(), "acd");
+    drop(guard);
+    assert_eq!(target, "a");
+    assert_eq!(history.branches().count(), 1);
+    assert_eq!(history.validate(), Ok(()));
+
+    history.redo(&mut target).unwrap();
+    assert_eq!(target, "ab");
+}
+
+#[test]
+fn queue_commit_failed() {
+    struct Push(u8);
+
+    impl undo::Edit for Push {
+        type Target = heapless::Vec<u8, 4>;
+        type Output = Result<(), u8>;
+
+        fn edit(&mut self, target: &mut Self::Target) -> Result<(), u8> {
+            target.push(self.0)
+        }
+
+        fn undo(&mut self, target: &mut Self::Target) -> Result<(), u8> {
+            target.pop().map(drop).ok_or(self.0)
+        }
+    }
+
+    let mut target = heapless::Vec::new();
+    let mut history = History::<_, 32>::new();
+    history.edit(&mut target, Push(1)).unwrap();
+    history.edit(&mut target, Push(2)).unwrap();
+    history.undo(&mut target).unwrap().unwrap();
+    history.edit(&mut target, Push(3)).unwrap();
+    history.set_saved();
+    let head = history.head();
+
+    let mut queue = history.queue::<16>();
+    queue.go_to(At::new(0, 2));
+    queue.clear_saved();
+    queue.edit(Push(4));
+    queue.clear();
+    queue.edit(Push(5));
+    queue.edit(Push(6));
+    assert_eq!(queue.try_commit(&mut target), Err(6));
+    assert_eq!(&target[..], &[1, 3]);
+    assert_eq!(history.head(), head);
+    assert_eq!(history.saved(), Some(head));
+    assert_eq!(history.branches().count(), 2);
+
+    let mut queue = history.queue::<16>();
+    queue.undo();
+    queue.edit(Push(4));
+    assert!(queue.try_commit(&mut target).is_ok());
+    assert_eq!(&target[..], &[1, 4]);
+    assert_eq!(history.branches().count(), 3);
+
+    // A branch that was created in the queue is removed again.
+    let mut target = heapless::Vec::new();
+    let mut history = History::<_, 32>::new();
+    history.edit(&mut target, Push(1)).unwrap();
+    history.edit(&mut target, Push(2)).unwrap();
+    history.undo(&mut target).unwrap().unwrap();
+    let head = history.head();
+
+    let mut queue = history.queue::<16>();
+    for push in [Push(3), Push(4), Push(5), Push(6)] {
+        queue.edit(push);
+    }
+    assert_eq!(queue.try_commit(&mut target), Err(6));
+    assert_eq!(&target[..], &[1]);
+    assert_eq!(history.validate(), Ok(()));
+    assert_eq!(history.head(), head);
+    assert_eq!(history.branches().count(), 1);
+    history.redo(&mut target).unwrap().unwrap();
+    assert_eq!(&target[..], &[1, 2]);
+
+    // A go to only takes up one entry in the checkpoint, no matter how far it goes.
+    let mut target = heapless::Vec::new();
+    let mut history = History::<_, 32>::new();
+    for n in 1..=4 {
+        history.edit(&mut target, Push(n)).unwrap();
+    }
+    history.go_to(&mut target, At::new(history.head().root, 1));
+    history.edit(&mut target, Push(5)).unwrap();
+    let old = history.head();
+    let mut queue = history.queue::<1>();
+    queue.go_to(At::new(1 - old.root, 4));
+    assert!(queue.try_commit(&mut target).is_ok());
+    assert_eq!(&target[..], &[1, 2, 3, 4]);
+
+    // A failed go to moves back to where it started.
+    let mut queue = history.queue::<4>();
+    queue.go_to(old);
+    for push in [Push(6), Push(7), Push(8)] {
+        queue.edit(push);
+    }
+    assert_eq!(queue.try_commit(&mut target), Err(8));
+    assert_eq!(&target[..], &[1, 2, 3, 4]);
+    assert_eq!(history.validate(), Ok(()));
+}
+
+#[test]
+fn undo_n_and_redo_n() {
+    let mut target = String::<256>::new();
+    let mut history = History::<_, 32>::new();
+    history.edit(&mut target, A);
+    history.edit(&mut target, B);
+    history.edit(&mut target, C);
+    let abc = history.head();
+
+    assert_eq!(history.undo_n(&mut target, 2).len(), 2);
+    assert_eq!(target, "a");
+    history.edit(&mut target, D);
+    assert_eq!(target, "ad");
+
+    // Only the edits in the current branch are undone and redone.
+    assert_eq!(history.undo_n(&mut target, 5).len(), 2);
+    assert_eq!(target, "");
+    assert_eq!(history.redo_n(&mut target, 1).len(), 1);
+    assert_eq!(target, "a");
+    assert_eq!(history.redo_all(&mut target).len(), 1);
+    assert_eq!(target, "ad");
+
+    history.go_to(&mut target, abc);
+    assert_eq!(target, "abc");
+    assert_eq!(history.undo_all(&mut target).len(), 3);
+    assert!(history.undo_n(&mut target, 1).is_empty());
+    assert_eq!(history.redo_n(&mut target, 5).len(), 3);
+    assert_eq!(target, "abc");
+}
+
+#[test]
+fn amend() {
+    let mut target = String::<256>::new();
+    let mut history = History::<_, 32>::new();
+    history.edit(&mut target, A);
+    history.edit(&mut target, B);
+    history.edit(&mut target, C);
+    history.set_saved();
+    history.undo(&mut target).unwrap();
+    history.edit(&mut target, D);
+    history.undo(&mut target).unwrap();
+    assert_eq!(target, "ab");
+
+    history.amend(&mut target, |edit| *edit = E).unwrap();
+    assert_eq!(target, "ae");
+    assert_eq!(history.saved(), None);
+    assert_eq!(history.len(), 3);
+    assert_eq!(history.branches().count(), 2);
+
+    history.redo(&mut target).unwrap();
+    assert_eq!(target, "aed");
+
+    // The active edit can not be amended past the barrier.
+    history.set_barrier();
+    assert!(history.amend(&mut target, |edit| *edit = F).is_none());
+    history.clear_barrier();
+    history.amend(&mut target, |edit| *edit = F).unwrap();
+    assert_eq!(target, "aef");
+}
+
+#[test]
+fn squash() {
+    enum Op {
+        Add(Add<256>),
+        Batch(Vec<Op>),
+    }
+
+    impl undo::Edit for Op {
+        type Target = String<256>;
+        type Output = ();
+
+        fn edit(&mut self, target: &mut String<256>) {
+            match self {
+                Op::Add(add) => add.edit(target),
+                Op::Batch(ops) => ops.iter_mut().for_each(|op| op.edit(target)),
+            }
+        }
+
+        fn undo(&mut self, target: &mut String<256>) {
+            match self {
+                Op::Add(add) => add.undo(target),
+                Op::Batch(ops) => ops.iter_mut().rev().for_each(|op| op.undo(target)),
+            }
+        }
+    }
+
+    impl FromIterator<Op> for Op {
+        fn from_iter<T: IntoIterator<Item = Op>>(iter: T) -> Self {
+            Op::Batch(iter.into_iter().collect())
+        }
+    }
+
+    let mut target = String::<256>::new();
+    let mut history = History::<_, 32>::new();
+    for add in [A, B, C, D, E] {
+        history.edit(&mut target, Op::Add(add));
+    }
+    let abcde = history.head();
+    history.undo(&mut target);
+    history.edit(&mut target, Op::Add(G));
+    let abcdg = history.head();
+    history.go_to(&mut target, abcde);
+    history.go_to(&mut target, At::new(abcde.root, 2));
+    history.edit(&mut target, Op::Add(F));
+    let abf = history.head();
+    history.go_to(&mut target, abcde);
+    assert_eq!(target, "abcde");
+    assert_eq!(history.branches().count(), 3);
+
+    assert!(!history.squash(1..6));
+    // The head is inside the range.
+    history.undo(&mut target);
+    assert!(!history.squash(1..5));
+    history.redo(&mut target);
+    // The range is split at the fork of the `abf` branch.
+    assert!(history.squash(1..4));
+    assert_eq!(history.len(), 4);
+    assert_eq!(history.head(), At::new(0, 4));
+    assert_eq!(history.branches().count(), 3);
+
+    history.undo(&mut target);
+    assert_eq!(target, "abcd");
+    history.undo(&mut target);
+    assert_eq!(target, "ab");
+    history.go_to(&mut target, abf);
+    assert_eq!(target, "abf");
+    history.go_to(&mut target, At::new(abcdg.root, abcdg.index - 1));
+    assert_eq!(target, "abcdg");
+    history.go_to(&mut target, At::new(abcde.root, 4));
+    assert_eq!(target, "abcde");
+
+    // Squashing a range without forks leaves a single entry.
+    let abcdg = At::new(abcdg.root, abcdg.index - 1);
+    history.go_to(&mut target, abcdg);
+    assert!(history.squash_with(..2, |edits| Op::Batch(edits.into_iter().collect())));
+    assert_eq!(history.len(), 3);
+    assert_eq!(history.branches().count(), 3);
+    history.undo(&mut target);
+    assert_eq!(target, "abcd");
+    history.undo(&mut target);
+    assert_eq!(target, "ab");
+    history.go_to(&mut target, At::new(abf.root, abf.index - 1));
+    assert_eq!(target, "abf");
+    history.go_to(&mut target, At::new(abcdg.root, 0));
+    assert_eq!(target, "");
+}
+
+#[test]
+fn truncate_front() {
+    let mut target = String::<256>::new();
+    let mut history = History::<_, 32>::new();
+    history.edit(&mut target, A);
+    history.edit(&mut target, E);
+    history.undo(&mut target).unwrap();
+    history.edit(&mut target, B);
+    history.edit(&mut target, C);
+    history.edit(&mut target, F);
+    history.set_saved();
+    history.undo(&mut target).unwrap();
+    history.edit(&mut target, D);
+    assert_eq!(target, "abcd");
+    assert_eq!(history.branches().count(), 3);
+
+    let root = history.head().root;
+    assert_eq!(history.truncate_front(2), 2);
+    assert_eq!(history.head(), At::new(root, 2));
+    assert_eq!(history.branches().count(), 2);
+    let saved = history.saved().unwrap();
+    assert_eq!(saved.index, 2);
+
+    history.go_to(&mut target, saved);
+    assert_eq!(target, "abcf");
+    history.undo(&mut target).unwrap();
+    history.undo(&mut target).unwrap();
+    assert_eq!(target, "ab");
+    assert!(!history.can_undo());
+}
+
+#[test]
+fn discard_redo() {
+    let mut target = String::<256>::new();
+    let mut history = History::<_, 32>::new();
+    history.edit(&mut target, A);
+    history.edit(&mut target, B);
+    history.edit(&mut target, C);
+    history.set_saved();
+    history.undo(&mut target).unwrap();
+    history.edit(&mut target, D);
+    history.undo(&mut target).unwrap();
+    history.undo(&mut target).unwrap();
+    assert_eq!(history.branches().count(), 2);
+
+    history.discard_redo(true);
+    assert!(!history.can_redo());
+    assert_eq!(history.branches().count(), 3);
+    let saved = history.saved().unwrap();
+    history.go_to(&mut target, saved);
+    assert_eq!(target, "abc");
+    history.go_to(&mut target, At::new(history.head().root, 1));
+    history.discard_redo(false);
+    assert_eq!(history.branches().count(), 1);
+    assert_eq!(history.saved(), None);
+    assert_eq!(target, "a");
+}
+
+#[test]
+fn on_evict() {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use undo::Evicted;
+
+    static ENTRIES: AtomicUsize = AtomicUsize::new(0);
+    static BRANCHES: AtomicUsize = AtomicUsize::new(0);
+    fn on_evict(evicted: Evicted<Add<256>, 32>) {
+        match evicted {
+            Evicted::Entry(_) => ENTRIES.fetch_add(1, Ordering::Relaxed),
+            Evicted::Branch(_) => BRANCHES.fetch_add(1, Ordering::Relaxed),
+        };
+    }
+
+    let mut target = String::<256>::new();
+    let mut history = History::<_, 32>::builder()
+        .limit(3)
+        .on_evict(on_evict)
+        .build();
+    history.edit(&mut target, A);
+    history.undo(&mut target).unwrap();
+    history.edit(&mut target, B);
+    history.edit(&mut target, C);
+    history.undo(&mut target).unwrap();
+    history.edit(&mut target, D);
+    assert_eq!(ENTRIES.load(Ordering::Relaxed), 0);
+    assert_eq!(history.branches().count(), 3);
+
+    // The first entry is evicted together with the branch that was split off before it.
+    history.edit(&mut target, E);
+    history.edit(&mut target, F);
+    assert_eq!(ENTRIES.load(Ordering::Relaxed), 1);
+    assert_eq!(BRANCHES.load(Ordering::Relaxed), 1);
+    assert_eq!(history.branches().count(), 2);
+
+    history.go_to(&mut target, At::new(history.head().root, 0));
+    history.clear();
+    assert_eq!(ENTRIES.load(Ordering::Relaxed), 4);
+    assert_eq!(BRANCHES.load(Ordering::Relaxed), 2);
+    assert_eq!(target, "b");
+}
+
+#[test]
+fn pin() {
+    struct Text(&'static str);
+
+    impl undo::Edit for Text {
+        type Target = std::string::String;
+        type Output = ();
+
+        fn edit(&mut self, target: &mut std::string::String) {
+            target.push_str(self.0);
+        }
+
+        fn undo(&mut self, target: &mut std::string::String) {
+            target.truncate(target.len() - self.0.len());
+        }
+
+        fn merge(&mut self, other: Self) -> undo::Merged<Self> {
+            self.0 = match (self.0, other.0) {
+                ("b", "c") => "bc",
+                _ => return undo::Merged::No(other),
+            };
+            undo::Merged::Yes
+        }
+    }
+
+    let mut target = std::string::String::new();
+    let mut history = History::<_, 32>::builder().limit(4).build();
+    history.edit(&mut target, Text("a"));
+    assert!(history.pin(0));
+    // Saving prevents the edits from being merged when they are pushed.
+    for text in ["b", "c", "d"] {
+        history.set_saved();
+        history.edit(&mut target, Text(text));
+    }
+    let abcd = history.head();
+    history.undo(&mut target).unwrap();
+    history.edit(&mut target, Text("e"));
+    history.set_saved();
+    history.edit(&mut target, Text("f"));
+    assert_eq!(target, "abcef");
+    assert_eq!(history.len(), 4);
+
+    // The branch that was split off after the merged edits is moved with them.
+    let abcd = At::new(abcd.root, abcd.index - 1);
+    history.go_to(&mut target, abcd);
+    assert_eq!(target, "abcd");
+    assert_eq!(history.head(), abcd);
+    history.undo(&mut target).unwrap();
+    assert_eq!(target, "abc");
+    history.undo(&mut target).unwrap();
+    assert_eq!(target, "a");
+    history.undo(&mut target).unwrap();
+    assert_eq!(target, "");
+}
+
+#[test]
+fn barrier() {
+    let mut target = String::<256>::new();
+    let mut history = History::<_, 32>::new();
+    history.edit(&mut target, A);
+    history.edit(&mut target, B);
+    let ab = history.head();
+    history.undo(&mut target).unwrap();
+    history.edit(&mut target, C);
+    history.edit(&mut target, D);
+    history.set_barrier();
+    assert_eq!(history.barrier(), Some(history.head()));
+
+    // The branch was split off before the barrier.
+    assert!(history.go_to(&mut target, ab).is_empty());
+    assert!(history.undo(&mut target).is_none());
+    assert_eq!(target, "acd");
+
+    history.clear_barrier();
+    history.go_to(&mut target, ab);
+    assert_eq!(target, "ab");
+}
+
+#[test]
+fn byte_budget() {
+    struct Paste(&'static str);
+
+    impl undo::Edit for Paste {
+        type Target = std::string::String;
+        type Output = ();
+
+        fn edit(&mut self, target: &mut std::string::String) {
+            target.push_str(self.0);
+        }
+
+        fn undo(&mut self, target: &mut std::string::String) {
+            target.truncate(target.len() - self.0.len());
+        }
+    }
+
+    impl undo::EditSize for Paste {
+        fn size(&self) -> usize {
+            self.0.len()
+        }
+    }
+
+    let mut target = std::string::String::new();
+    let mut history = History::<_, 32>::builder().byte_budget(10).build();
+    history.edit(&mut target, Paste("abc"));
+    history.edit(&mut target, Paste("def"));
+    let abcdef = history.head();
+    history.undo(&mut target).unwrap();
+    history.edit(&mut target, Paste("ghijkl"));
+    // The oldest edit is removed and the branch is moved with the states after it.
+    assert_eq!(history.len(), 1);
+    assert_eq!(history.total_size(), 9);
+    let ghijkl = history.head();
+    history.go_to(&mut target, At::new(abcdef.root, abcdef.index - 1));
+    assert_eq!(target, "abcdef");
+    // Moving between the branches does not count the edits twice.
+    history.go_to(&mut target, ghijkl);
+    assert_eq!(target, "abcghijkl");
+    history.edit(&mut target, Paste("m"));
+    assert_eq!(history.branches().count(), 2);
+    assert_eq!(history.total_size(), 10);
+
+    // The branch is removed when the current root branch can not be shortened more.
+    let mut target = std::string::String::new();
+    let mut history = History::<_, 32>::builder().byte_budget(10).build();
+    history.edit(&mut target, Paste("ab"));
+    history.edit(&mut target, Paste("cdefgh"));
+    history.undo(&mut target).unwrap();
+    history.edit(&mut target, Paste("ijklmn"));
+    assert_eq!(history.len(), 1);
+    assert_eq!(history.branches().count(), 1);
+    assert_eq!(history.total_size(), 6);
+    assert_eq!(target, "abijklmn");
+}
+
+#[test]
+fn max_age() {
+    use std::time::{Duration, SystemTime};
+
+    let mut target = String::<256>::new();
+    let mut history = History::<_, 32>::builder()
+        .max_age(Duration::from_secs(60))
+        .build();
+    history.edit(&mut target, A);
+    history.edit(&mut target, B);
+    history.undo(&mut target).unwrap();
+    history.edit(&mut target, C);
+    history.undo(&mut target).unwrap();
+    history.edit(&mut target, D);
+    history.undo(&mut target).unwrap();
+    assert_eq!(history.branches().count(), 3);
+
+    let later = SystemTime::now() + Duration::from_secs(120);
+    // The expired edit after the head is removed too.
+    assert_eq!(history.enforce_retention(later), 2);
+    assert!(history.is_empty());
+    assert_eq!(history.branches().count(), 1);
+    assert_eq!(target, "a");
+
+    // Moving between the branches does not remove the expired edits.
+    let mut target = String::<256>::new();
+    let mut history = History::<_, 32>::builder()
+        .max_age(Duration::from_millis(50))
+        .build();
+    history.edit(&mut target, A);
+    history.edit(&mut target, B);
+    let ab = history.head();
+    history.undo(&mut target).unwrap();
+    history.edit(&mut target, C);
+    std::thread::sleep(Duration::from_millis(60));
+    history.go_to(&mut target, ab);
+    assert_eq!(target, "ab");
+    assert_eq!(history.len(), 2);
+    history.edit(&mut target, D);
+    assert_eq!(history.len(), 1);
+    assert_eq!(history.branches().count(), 1);
+}
+
+#[test]
+fn keyframes() {
+    let mut target = String::<256>::new();
+    let mut history = History::<_, 32>::builder()
+        .keyframes(Keyframes::<_, 4>::new(2))
+        .build();
+    for edit in [A, B, C, D, E] {
+        history.edit(&mut target, edit);
+    }
+    let abcde = history.head();
+    history.undo_all(&mut target);
+    history.edit(&mut target, F);
+    let f = history.head();
+    assert_eq!(target, "f");
+
+    // Jumps to the snapshot at 4 in the other branch and only redoes one edit.
+    assert_eq!(history.go_to(&mut target, abcde).len(), 1);
+    assert_eq!(target, "abcde");
+    history.go_to(&mut target, f);
+    assert_eq!(target, "f");
+}
+
+#[test]
+fn replay_to() {
+    let mut target = String::<256>::new();
+    let mut history = History::<_, 32>::new();
+    history.edit(&mut target, A);
+    history.edit(&mut target, B);
+    history.edit(&mut target, C);
+    let abc = history.head();
+    history.undo(&mut target);
+    history.undo(&mut target);
+    history.edit(&mut target, D);
+    let ad = history.head();
+    history.undo(&mut target);
+    history.edit(&mut target, E);
+    history.edit(&mut target, F);
+
+    let mut fresh = String::<256>::new();
+    history.replay_to(abc, &mut fresh);
+    assert_eq!(fresh, "abc");
+    let mut fresh = String::<256>::new();
+    history.replay_to(ad, &mut fresh);
+    assert_eq!(fresh, "ad");
+    let mut fresh = String::<256>::new();
+    history.replay_to(At::new(abc.root, 2), &mut fresh);
+    assert_eq!(fresh, "ab");
+    let mut fresh = String::<256>::new();
+    history.replay_to(history.head(), &mut fresh);
+    assert_eq!(fresh, target);
+    assert_eq!(target, "aef");
+    assert!(history
+        .replay_to(At::new(abc.root, 4), &mut fresh)
+        .is_empty());
+}
+
+#[test]
+fn validate() {
+    let mut target = String::<256>::new();
+    let mut history = History::<_, 32>::new();
+    assert_eq!(history.validate(), Ok(()));
+    history.edit(&mut target, A);
+    history.edit(&mut target, B);
+    history.edit(&mut target, C);
+    history.set_saved();
+    let abc = history.head();
+    history.undo(&mut target).unwrap();
+    history.edit(&mut target, D);
+    history.undo(&mut target).unwrap();
+    history.undo(&mut target).unwrap();
+    history.edit(&mut target, E);
+    history.edit(&mut target, F);
+    let aef = history.head();
+    assert_eq!(history.validate(), Ok(()));
+
+    for at in [abc, aef, abc, At::new(abc.root, 1), aef] {
+        history.go_to(&mut target, at);
+        assert_eq!(history.validate(), Ok(()));
+    }
+    history.discard_redo(true);
+    history.truncate_front(1);
+    assert_eq!(history.validate(), Ok(()));
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn deserialize_invalid() {
+    let mut target = String::<256>::new();
+    let mut history = History::<_, 32>::new();
+    history.edit(&mut target, A);
+    history.edit(&mut target, B);
+    history.undo(&mut target);
+    history.edit(&mut target, C);
+
+    let mut json = serde_json::to_value(&history).unwrap();
+    let valid: History<Add<256>, 32> = serde_json::from_value(json.clone()).unwrap();
+    assert_eq!(valid.head(), history.head());
+    assert_eq!(valid.branches().count(), 2);
+
+    // Make the branch point to a slot that does not exist.
+    let root = history.head().root;
+    let branch = (0..2).find(|&id| id != root).unwrap();
+    let mut moved = json.clone();
+    let branches = moved["branches"].as_object_mut().unwrap();
+    let value = branches.remove(&branch.to_string()).unwrap();
+    branches.insert((1u64 << 40).to_string(), value);
+    let error = serde_json::from_value::<History<Add<256>, 32>>(moved).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        format!(
+            "the branch id {} is used twice or is out of bounds",
+            1u64 << 40
+        )
+    );
+
+    json["branches"][branch.to_string()]["parent"]["root"] = 9.into();
+    let error = serde_json::from_value::<History<Add<256>, 32>>(json).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        format!("the parent of branch {branch} does not exist or is out of bounds")
+    );
+
+    // Make a branch fork before the start of its parent branch.
+    let mut target = String::<256>::new();
+    let mut history = History::<_, 32>::new();
+    history.edit(&mut target, A);
+    history.edit(&mut target, B);
+    history.edit(&mut target, C);
+    let abc = history.head();
+    history.undo(&mut target);
+    history.edit(&mut target, D);
+    history.go_to(&mut target, abc);
+    history.go_to(&mut target, At::new(abc.root, 1));
+    history.edit(&mut target, E);
+    assert_eq!(history.validate(), Ok(()));
+
+    let mut json = serde_json::to_value(&history).unwrap();
+    let root = history.head().root;
+    let (branch, _) = history
+        .branches()
+        .find(|&(id, branch)| id != root && branch.parent().root != root)
+        .unwrap();
+    json["branches"][branch.to_string()]["parent"]["index"] = 0.into();
+    let error = serde_json::from_value::<History<Add<256>, 32>>(json).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        format!("the parent of branch {branch} does not exist or is out of bounds")
+    );
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn deserialize_with_slot() {
+    use undo::Event;
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let mut target = String::<256>::new();
+    let mut history = History::<_, 32, _>::builder().connect(sender).build();
+    history.edit(&mut target, A);
+    history.edit(&mut target, B);
+    history.undo(&mut target);
+    history.edit(&mut target, C);
+    let root = history.head().root;
+    let ab = At::new(history.branches().find(|&(id, _)| id != root).unwrap().0, 2);
+    assert!(receiver.try_iter().count() > 0);
+
+    let json = serde_json::to_string(&history).unwrap();
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let mut loaded: History<Add<256>, 32, _> =
+        History::deserialize_with_slot(&mut serde_json::Deserializer::from_str(&json), sender)
+            .unwrap();
+    assert_eq!(loaded.head(), history.head());
+    assert!(receiver.try_recv().is_err());
+
+    loaded.go_to(&mut target, ab);
+    assert_eq!(target, "ab");
+    assert!(receiver
+        .try_iter()
+        .any(|event| event == Event::Root(ab.root)));
+}
+
+#[test]
+fn export_import() {
+    use undo::history::{ImportError, VERSION};
+
+    let mut target = String::<256>::new();
+    let mut history = History::<_, 32>::new();
+    history.edit(&mut target, A);
+    history.edit(&mut target, B);
+    history.set_saved();
+    let ab = history.head();
+    history.undo(&mut target);
+    history.edit(&mut target, C);
+    history.edit(&mut target, D);
+
+    let export = history.export();
+    assert_eq!(export.version, VERSION);
+    let mut imported = History::<_, 32>::import(export.clone()).unwrap();
+    assert_eq!(imported.head(), history.head());
+    assert_eq!(imported.saved(), history.saved());
+    imported.go_to(&mut target, ab);
+    assert_eq!(target, "ab");
+    assert!(imported.is_saved());
+
+    // An older version is upgraded by the migration.
+    let migrated = History::<_, 32>::import_with(VERSION - 1, export.clone(), |version, old| {
+        assert_eq!(version, VERSION - 1);
+        let mut export = old;
+        for branch in &mut export.branches {
+            branch
+                .edits
+                .iter_mut()
+                .for_each(|edit| edit.0 = edit.0.to_ascii_uppercase());
+        }
+        Some(export)
+    })
+    .unwrap();
+    assert_eq!(migrated.redo_string::<8>(), None);
+    assert_eq!(migrated.undo_string::<8>().unwrap(), "Add 'D'");
+
+    let error = History::<Add<256>, 32>::import_with(VERSION - 1, (), |_, _| None).unwrap_err();
+    assert_eq!(error, ImportError::UnsupportedVersion(VERSION - 1));
+    let error = History::<Add<256>, 32>::import_with(VERSION + 1, (), |_, _| unreachable!());
+    assert_eq!(
+        error.unwrap_err(),
+        ImportError::UnsupportedVersion(VERSION + 1)
+    );
+
+    let mut new = export;
+    new.version = VERSION + 1;
+    let error = History::<Add<256>, 32>::import(new).unwrap_err();
+    assert_eq!(error, ImportError::UnsupportedVersion(VERSION + 1));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn import_v0() {
+    use serde::Deserialize;
+    use undo::history::{Export, ExportBranch, Header, VERSION};
+
+    // The first version only stored the edits in the root branch.
+    #[derive(Deserialize)]
+    struct V0 {
+        limit: usize,
+        index: usize,
+        edits: Vec<Add<256>>,
+    }
+
+    let json = r#"{"version":0,"limit":8,"index":1,"edits":["a","b"]}"#;
+    let header: Header = serde_json::from_str(json).unwrap();
+    let raw: serde_json::Value = serde_json::from_str(json).unwrap();
+    let mut history = History::<_, 32>::import_with(header.version, raw, |version, raw| {
+        if version != 0 {
+            return serde_json::from_value(raw).ok();
+        }
+        let v0 = V0::deserialize(raw).ok()?;
+        Some(Export {
+            version: VERSION,
+            limit: v0.limit,
+            head: At::new(0, v0.index),
+            saved: None,
+            branches: vec![ExportBranch {
+                id: 0,
+                parent: None,
+                edits: v0.edits.into_iter().collect(),
+            }],
+        })
+    })
+    .unwrap();
+    assert_eq!(history.limit(), 8);
+    assert_eq!(history.len(), 2);
+    assert_eq!(history.head(), At::new(0, 1));
+
+    let mut target = String::<256>::try_from("a").unwrap();
+    history.redo(&mut target).unwrap();
+    assert_eq!(target, "ab");
+}
+
+#[test]
+fn export_branch_ids() {
+    use undo::history::ImportError;
+    use undo::InvariantError;
+
+    // More branches than `N` are exported.
+    let mut target = String::<256>::new();
+    let mut history = History::<_, 2>::new();
+    for edit in [A, B, C, D, E, F] {
+        history.edit(&mut target, edit);
+        history.undo(&mut target);
+    }
+    assert_eq!(history.branches().count(), 6);
+    let export = history.export();
+    assert_eq!(export.branches.len(), 6);
+    let imported = History::<_, 2>::import(export.clone()).unwrap();
+    assert_eq!(imported.branches().count(), 6);
+
+    // The ids must be unique and below the number of branches.
+    let root = export.head.root;
+    let mut ids = (0..6).filter(|&id| id != root);
+    let (first, second) = (ids.next().unwrap(), ids.next().unwrap());
+    let mut invalid = export.clone();
+    invalid.branches[first].id = 1 << 40;
+    assert_eq!(
+        History::<Add<256>, 2>::import(invalid).unwrap_err(),
+        ImportError::Invalid(InvariantError::InvalidId(1 << 40))
+    );
+    let mut invalid = export;
+    invalid.branches[second].id = first;
+    assert_eq!(
+        History::<Add<256>, 2>::import(invalid).unwrap_err(),
+        ImportError::Invalid(InvariantError::InvalidId(first))
+    );
+
+    // Removed branches leave gaps in the ids, which are not part of the export.
+    let mut target = String::<256>::new();
+    let mut history = History::<_, 32>::new();
+    history.edit(&mut target, A);
+    history.undo(&mut target);
+    history.edit(&mut target, B);
+    history.edit(&mut target, C);
+    history.undo(&mut target);
+    history.edit(&mut target, D);
+    history.truncate_front(1);
+    let count = history.branches().count();
+    assert!(history.branches().any(|(id, _)| id >= count));
+
+    let mut imported = History::<_, 32>::import(history.export()).unwrap();
+    let root = imported.head().root;
+    let (id, branch) = imported.branches().find(|&(id, _)| id != root).unwrap();
+    let at = At::new(id, branch.parent().index + branch.len());
+    imported.go_to(&mut target, at);
+    assert_eq!(target, "bc");
+
+    #[cfg(feature = "serde")]
+    {
+        let json = serde_json::to_string(&history).unwrap();
+        let mut deserialized: History<Add<256>, 32> = serde_json::from_str(&json).unwrap();
+        let root = deserialized.head().root;
+        let (id, branch) = deserialized.branches().find(|&(id, _)| id != root).unwrap();
+        let at = At::new(id, branch.parent().index + branch.len());
+        deserialized.go_to(&mut target, at);
+        assert_eq!(target, "bc");
+    }
+}
+
+#[test]
+fn journal() {
+    use undo::journal::{self, Journal};
+
+    let mut target = String::<256>::new();
+    let mut journal = Journal::new(History::<_, 32>::new(), &target, Vec::new()).unwrap();
+    journal.edit(&mut target, A).unwrap();
+    journal.edit(&mut target, B).unwrap();
+    journal.set_saved().unwrap();
+    let ab = journal.history().head();
+    journal.undo(&mut target).unwrap();
+    journal.edit(&mut target, C).unwrap();
+    journal.go_to(&mut target, ab).unwrap();
+    journal.edit(&mut target, D).unwrap();
+    assert_eq!(target, "abd");
+
+    // The last record was torn by a crash.
+    let bytes = journal.writer().clone();
+    let torn = &bytes[..bytes.len() - 2];
+    let mut recovered = String::<256>::new();
+    let (mut history, len) =
+        journal::recover(torn, History::<_, 32>::builder(), &mut recovered).unwrap();
+    assert!(len < torn.len());
+    assert_eq!(recovered, "ab");
+    assert!(history.is_saved());
+    history.undo(&mut recovered).unwrap();
+    history.edit(&mut recovered, C);
+    assert_eq!(recovered, "ac");
+
+    // Compacting starts a new journal from a snapshot of the history.
+    journal.compact(&target, Vec::new()).unwrap();
+    journal.undo(&mut target).unwrap();
+    let mut recovered = String::<256>::new();
+    let (history, len) = journal::recover(
+        journal.writer(),
+        History::<Add<256>, 32>::builder(),
+        &mut recovered,
+    )
+    .unwrap();
+    assert_eq!(len, journal.writer().len());
+    assert_eq!(recovered, "ab");
+    assert_eq!(history.head(), journal.history().head());
+    assert_eq!(
+        history.branches().count(),
+        journal.history().branches().count()
+    );
+
+    // The snapshot has the target, so the edits removed by the limit are not needed.
+    let mut target = String::<256>::new();
+    let history = History::<_, 32>::builder().limit(2).build();
+    let mut journal = Journal::new(history, &target, Vec::new()).unwrap();
+    for edit in [A, B, C, D] {
+        journal.edit(&mut target, edit).unwrap();
+    }
+    journal.compact(&target, Vec::new()).unwrap();
+    journal.undo(&mut target).unwrap();
+    let mut recovered = String::<256>::new();
+    let (history, _) = journal::recover(
+        journal.writer(),
+        History::<Add<256>, 32>::builder(),
+        &mut recovered,
+    )
+    .unwrap();
+    assert_eq!(recovered, "abc");
+    assert_eq!(history.limit(), 2);
+    assert_eq!(history.len(), 2);
+
+    // The root is renumbered when the branch before it was removed.
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let mut target = String::<256>::new();
+    let history = History::<_, 32, _>::builder()
+        .limit(1)
+        .connect(sender)
+        .build();
+    let mut journal = Journal::new(history, &target, Vec::new()).unwrap();
+    journal.edit(&mut target, A).unwrap();
+    journal.undo(&mut target).unwrap();
+    journal.edit(&mut target, B).unwrap();
+    journal.edit(&mut target, C).unwrap();
+    assert_eq!(journal.history().branches().count(), 1);
+    let root = journal.history().head().root;
+    assert_ne!(root, 0);
+    receiver.try_iter().for_each(drop);
+    journal.compact(&target, Vec::new()).unwrap();
+    assert_eq!(journal.history().head().root, 0);
+    assert_eq!(receiver.try_iter().collect::<Vec<_>>(), [Event::Root(0)]);
+}
+
+#[test]
+fn journal_poisoned() {
+    use std::io::{self, Write};
+    use undo::journal::{self, Journal};
+
+    /// Fails to write once the buffer has `capacity` bytes, after writing what fits.
+    struct Bounded {
+        bytes: Vec<u8>,
+        capacity: usize,
+    }
+
+    impl Write for Bounded {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let len = buf.len().min(self.capacity - self.bytes.len());
+            if len == 0 {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+            self.bytes.extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut target = String::<256>::new();
+    let mut journal = Journal::new(History::<_, 32>::new(), &target, Vec::new()).unwrap();
+    journal.edit(&mut target, A).unwrap();
+    // Room for the snapshot, the first edit, and a part of the second edit.
+    let capacity = journal.writer().len() + 4;
+
+    let mut target = String::<256>::new();
+    let writer = Bounded {
+        bytes: Vec::new(),
+        capacity,
+    };
+    let mut journal = Journal::new(History::<_, 32>::new(), &target, writer).unwrap();
+    journal.edit(&mut target, A).unwrap();
+    // The record is only partly written, so the journal is poisoned.
+    assert!(journal.edit(&mut target, B).is_err());
+    assert!(journal.is_poisoned());
+    let len = journal.writer().bytes.len();
+    assert!(journal.undo(&mut target).is_err());
+    assert_eq!(journal.writer().bytes.len(), len);
+    assert_eq!(target, "a");
+
+    // Compacting into a new writer starts over from a snapshot.
+    let writer = Bounded {
+        bytes: Vec::new(),
+        capacity: 256,
+    };
+    journal.compact(&target, writer).unwrap();
+    assert!(!journal.is_poisoned());
+    journal.edit(&mut target, B).unwrap();
+    let mut recovered = String::<256>::new();
+    journal::recover(
+        &journal.writer().bytes,
+        History::<Add<256>, 32>::builder(),
+        &mut recovered,
+    )
+    .unwrap();
+    assert_eq!(recovered, "ab");
+}
+
+#[test]
+#[cfg(feature = "binary")]
+fn binary() {
+    use undo::Codec;
+
+    let mut target = String::<256>::new();
+    let mut history = History::<_, 32>::new();
+    history.edit(&mut target, A);
+    history.edit(&mut target, B);
+    history.set_saved();
+    let ab = history.head();
+    history.undo(&mut target);
+    history.edit(&mut target, C);
+
+    let mut buf = [0; 512];
+    let len = history.encode(&mut buf).unwrap();
+    let (mut decoded, read) = History::<Add<256>, 32>::decode(&buf[..len]).unwrap();
+    assert_eq!(read, len);
+    assert_eq!(decoded.head(), history.head());
+    assert_eq!(decoded.saved(), history.saved());
+    decoded.go_to(&mut target, ab);
+    assert_eq!(target, "ab");
+    assert!(decoded.is_saved());
+
+    let len = ab.encode(&mut buf).unwrap();
+    assert_eq!(At::decode(&buf[..len]), Some((ab, len)));
+
+    // More branches than `N` are decoded.
+    let mut history = History::<_, 2>::new();
+    for edit in [A, B, C, D, E, F] {
+        history.edit(&mut target, edit);
+        history.undo(&mut target);
+    }
+    let len = history.encode(&mut buf).unwrap();
+    let (decoded, _) = History::<Add<256>, 2>::decode(&buf[..len]).unwrap();
+    assert_eq!(decoded.branches().count(), 6);
+
+    // Removed branches leave gaps in the ids, which are compacted when encoded.
+    let mut target = String::<256>::new();
+    let mut history = History::<_, 32>::new();
+    history.edit(&mut target, A);
+    history.undo(&mut target);
+    history.edit(&mut target, B);
+    history.edit(&mut target, C);
+    history.undo(&mut target);
+    history.edit(&mut target, D);
+    history.truncate_front(1);
+    let count = history.branches().count();
+    assert!(history.branches().any(|(id, _)| id >= count));
+    let len = history.encode(&mut buf).unwrap();
+    let (mut decoded, _) = History::<Add<256>, 32>::decode(&buf[..len]).unwrap();
+    let root = decoded.head().root;
+    let (id, branch) = decoded.branches().find(|&(id, _)| id != root).unwrap();
+    let at = At::new(id, branch.parent().index + branch.len());
+    decoded.go_to(&mut target, at);
+    assert_eq!(target, "bc");
+
+    // A branch id that is out of bounds is rejected.
+    let mut history = History::<_, 32>::new();
+    history.edit(&mut target, A);
+    history.undo(&mut target);
+    history.edit(&mut target, B);
+    let len = history.encode(&mut buf).unwrap();
+    let record = undo::Record::from(history).encode(&mut [0; 512]).unwrap();
+    // The id of the branch is after the root, the saved state, the record, and the count.
+    let at = 2 + record + 1;
+    assert_eq!(buf[at], 1 - buf[0]);
+    let mut bytes = buf[..at].to_vec();
+    let mut id = 1u64 << 40;
+    while id >= 0x80 {
+        bytes.push(id as u8 | 0x80);
+        id >>= 7;
+    }
+    bytes.push(id as u8);
+    bytes.extend_from_slice(&buf[at + 1..len]);
+    assert!(History::<Add<256>, 32>::decode(&bytes).is_none());
+    // The id of the root branch is used twice.
+    let mut bytes = buf[..len].to_vec();
+    bytes[at] = bytes[0];
+    assert!(History::<Add<256>, 32>::decode(&bytes).is_none());
+}
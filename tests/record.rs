@@ -1,5 +1,6 @@
 use heapless::String;
-use undo::{Add, Record};
+use undo::record::QueueOutput;
+use undo::{Add, Event, Keyframes, Record};
 
 const A: Add<256> = Add('a');
 const B: Add<256> = Add('b');
@@ -74,3 +75,1005 @@ fn checkpoint_saved() {
     assert!(record.is_saved());
     assert_eq!(target, "abc");
 }
+
+#[test]
+fn checkpoint_savepoint() {
+    let mut target = String::new();
+    let mut record = Record::<_, 32>::new();
+    record.edit(&mut target, A);
+    record.edit(&mut target, B);
+    record.set_saved();
+    record.undo(&mut target).unwrap();
+
+    let mut cp = record.checkpoint::<16>();
+    let outer = cp.savepoint();
+    cp.edit(&mut target, C);
+    let inner = cp.savepoint();
+    cp.edit(&mut target, D);
+    cp.edit(&mut target, E);
+    assert_eq!(target, "acde");
+
+    cp.rollback_to(&mut target, inner);
+    assert_eq!(target, "ac");
+    cp.edit(&mut target, F);
+    cp.release(inner);
+    assert!(cp.rollback_to(&mut target, inner).is_empty());
+    assert_eq!(target, "acf");
+
+    cp.rollback_to(&mut target, outer);
+    assert_eq!(target, "a");
+    cp.commit();
+    record.redo(&mut target).unwrap();
+    assert!(record.is_saved());
+    assert_eq!(target, "ab");
+}
+
+#[test]
+fn checkpoint_savepoint_released() {
+    let mut target = String::new();
+    let mut record = Record::<_, 32>::new();
+    let mut cp = record.checkpoint::<16>();
+    cp.edit(&mut target, A);
+    let old = cp.savepoint();
+    cp.release(old);
+
+    // A new savepoint at the same position does not make the old one active.
+    let new = cp.savepoint();
+    cp.edit(&mut target, B);
+    assert!(cp.rollback_to(&mut target, old).is_empty());
+    assert_eq!(target, "ab");
+    cp.rollback_to(&mut target, new);
+    assert_eq!(target, "a");
+}
+
+#[test]
+fn guard() {
+    fn edit_all(record: &mut Record<Add<256>, 32>, target: &mut String<256>) -> Option<()> {
+        let mut guard = record.guard::<16>(target);
+        guard.edit(A);
+        guard.edit(B);
+        guard.undo()?;
+        guard.undo()?;
+        guard.undo()?;
+        guard.commit();
+        Some(())
+    }
+
+    let mut target = String::new();
+    let mut record = Record::<_, 32>::new();
+    record.edit(&mut target, C);
+    assert!(edit_all(&mut record, &mut target).is_some());
+    assert_eq!(target, "");
+
+    record.clear();
+    record.edit(&mut target, D);
+    record.undo(&mut target).unwrap();
+    assert!(edit_all(&mut record, &mut target).is_none());
+    assert_eq!(target, "");
+    record.redo(&mut target).unwrap();
+    assert_eq!(target, "d");
+}
+
+#[test]
+fn queue_navigation() {
+    let mut target = String::new();
+    let mut record = Record::<_, 32>::new();
+    let mut queue = record.queue::<16>();
+    queue.edit(A);
+    queue.edit(B);
+    queue.set_saved();
+    queue.edit(C);
+    queue.go_to(1);
+    queue.revert();
+    queue.undo();
+    let outputs = queue.commit(&mut target);
+    assert_eq!(target, "a");
+    assert_eq!(
+        &outputs[..],
+        &[
+            QueueOutput::Edit(()),
+            QueueOutput::Edit(()),
+            QueueOutput::SetSaved,
+            QueueOutput::Edit(()),
+            QueueOutput::GoTo([(), ()].into_iter().collect()),
+            QueueOutput::Revert([()].into_iter().collect()),
+            QueueOutput::Undo(Some(())),
+        ][..]
+    );
+
+    let mut queue = record.queue::<16>();
+    queue.clear_saved();
+    queue.clear();
+    queue.redo();
+    let outputs = queue.commit(&mut target);
+    assert_eq!(
+        &outputs[..],
+        &[
+            QueueOutput::ClearSaved,
+            QueueOutput::Clear,
+            QueueOutput::Redo(None),
+        ][..]
+    );
+    assert!(record.is_empty());
+    assert!(!record.is_saved());
+}
+
+#[test]
+fn queue_premerge() {
+    struct Type(String<64>);
+
+    impl undo::Edit for Type {
+        type Target = String<256>;
+        type Output = ();
+
+        fn edit(&mut self, target: &mut String<256>) {
+            target.push_str(&self.0).unwrap();
+        }
+
+        fn undo(&mut self, target: &mut String<256>) {
+            let len = target.len() - self.0.len();
+            target.truncate(len);
+        }
+
+        fn merge(&mut self, other: Self) -> undo::Merged<Self> {
+            self.0.push_str(&other.0).unwrap();
+            undo::Merged::Yes
+        }
+    }
+
+    let mut target = String::new();
+    let mut record = Record::<_, 32>::new();
+    let mut queue = record.queue::<2>();
+    queue.premerge(true);
+    for c in "hello world".chars() {
+        let mut s = String::new();
+        s.push(c).unwrap();
+        queue.edit(Type(s));
+    }
+    let outputs = queue.commit(&mut target);
+    assert_eq!(outputs.len(), 1);
+    assert_eq!(target, "hello world");
+    assert_eq!(record.len(), 1);
+    record.undo(&mut target).unwrap();
+    assert_eq!(target, "");
+}
+
+#[test]
+fn queue_commit_merged() {
+    struct Count(usize);
+
+    impl undo::Edit for Count {
+        type Target = usize;
+        type Output = usize;
+
+        fn edit(&mut self, target: &mut usize) -> usize {
+            *target += self.0;
+            *target
+        }
+
+        fn undo(&mut self, target: &mut usize) -> usize {
+            *target -= self.0;
+            *target
+        }
+
+        fn merge(&mut self, other: Self) -> undo::Merged<Self> {
+            self.0 += other.0;
+            undo::Merged::Yes
+        }
+    }
+
+    let mut target = 0;
+    let mut record = Record::<_, 32>::new();
+    record.edit(&mut target, Count(1));
+    let mut queue = record.queue::<16>();
+    queue.edit(Count(2));
+    queue.edit(Count(3));
+    let outputs = queue.commit(&mut target);
+    assert_eq!(
+        &outputs[..],
+        &[QueueOutput::Edit(3), QueueOutput::Edit(6)][..]
+    );
+    assert_eq!(record.len(), 1);
+    record.undo(&mut target);
+    assert_eq!(target, 0);
+}
+
+#[test]
+fn queue_commit_failed() {
+    struct Op(&'static str);
+
+    impl undo::Edit for Op {
+        type Target = String<256>;
+        type Output = Result<(), char>;
+
+        fn edit(&mut self, target: &mut String<256>) -> Result<(), char> {
+            match self.0.strip_prefix('!') {
+                Some(_) => Err('!'),
+                None => target.push_str(self.0).map_err(|_| ' '),
+            }
+        }
+
+        fn undo(&mut self, target: &mut String<256>) -> Result<(), char> {
+            match self.0.strip_prefix('?') {
+                Some(_) => Err('?'),
+                None => {
+                    target.truncate(target.len() - self.0.len());
+                    Ok(())
+                }
+            }
+        }
+
+        fn merge(&mut self, other: Self) -> undo::Merged<Self> {
+            if self.0 == other.0 {
+                undo::Merged::Yes
+            } else {
+                undo::Merged::No(other)
+            }
+        }
+    }
+
+    let mut target = String::new();
+    let mut record = Record::<_, 32>::new();
+    record.edit(&mut target, Op("a")).unwrap();
+
+    // The failed edit is not pushed and the queued edit is not merged into the record.
+    let mut queue = record.queue::<16>();
+    queue.edit(Op("a"));
+    queue.edit(Op("!"));
+    assert_eq!(queue.try_commit(&mut target), Err('!'));
+    assert_eq!(target, "a");
+    assert_eq!(record.len(), 1);
+    record.undo(&mut target).unwrap().unwrap();
+    assert_eq!(target, "");
+    record.redo(&mut target).unwrap().unwrap();
+
+    // The index is moved back after the failed undo without calling the edit again.
+    record.edit(&mut target, Op("?b")).unwrap();
+    record.set_saved();
+    let mut queue = record.queue::<16>();
+    queue.edit(Op("c"));
+    queue.undo();
+    queue.undo();
+    assert_eq!(queue.try_commit(&mut target), Err('?'));
+    assert_eq!(target, "a?b");
+    assert_eq!(record.head(), 2);
+    assert_eq!(record.len(), 2);
+    assert!(record.is_saved());
+
+    // The go to stops at the failed step and is not rolled back through it.
+    record.edit(&mut target, Op("c")).unwrap();
+    let mut queue = record.queue::<16>();
+    queue.go_to(0);
+    assert_eq!(queue.try_commit(&mut target), Err('?'));
+    assert_eq!(target, "a?bc");
+    assert_eq!(record.head(), 3);
+
+    // A go to only takes up one entry in the checkpoint, no matter how far it goes.
+    let mut target = String::new();
+    let mut record = Record::<_, 32>::new();
+    for s in ["a", "b", "c", "d", "e", "f", "g", "h"] {
+        record.edit(&mut target, Op(s)).unwrap();
+    }
+    let mut queue = record.queue::<2>();
+    queue.go_to(0);
+    queue.go_to(8);
+    assert!(queue.try_commit(&mut target).is_ok());
+    assert_eq!(target, "abcdefgh");
+    assert_eq!(record.head(), 8);
+}
+
+#[test]
+fn undo_n_and_redo_n() {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let mut target = String::new();
+    let mut record = Record::<_, 32, _>::builder().connect(sender).build();
+    record.edit(&mut target, A);
+    record.edit(&mut target, B);
+    record.edit(&mut target, C);
+    receiver.try_iter().for_each(drop);
+
+    assert_eq!(record.undo_n(&mut target, 2).len(), 2);
+    assert_eq!(target, "a");
+    assert_eq!(
+        receiver.try_iter().collect::<Vec<_>>(),
+        [Event::Redo(true), Event::Index(1)]
+    );
+
+    assert_eq!(record.undo_n(&mut target, 5).len(), 1);
+    assert_eq!(record.redo_n(&mut target, 5).len(), 3);
+    assert_eq!(target, "abc");
+    assert_eq!(record.undo_all(&mut target).len(), 3);
+    assert!(record.undo_n(&mut target, 1).is_empty());
+    assert_eq!(record.redo_all(&mut target).len(), 3);
+    assert_eq!(target, "abc");
+}
+
+#[test]
+fn squash() {
+    enum Op {
+        Add(Add<256>),
+        Batch(Vec<Op>),
+    }
+
+    impl undo::Edit for Op {
+        type Target = String<256>;
+        type Output = ();
+
+        fn edit(&mut self, target: &mut String<256>) {
+            match self {
+                Op::Add(add) => add.edit(target),
+                Op::Batch(ops) => ops.iter_mut().for_each(|op| op.edit(target)),
+            }
+        }
+
+        fn undo(&mut self, target: &mut String<256>) {
+            match self {
+                Op::Add(add) => add.undo(target),
+                Op::Batch(ops) => ops.iter_mut().rev().for_each(|op| op.undo(target)),
+            }
+        }
+    }
+
+    let mut target = String::<256>::new();
+    let mut record = Record::<_, 32>::new();
+    for add in [A, B, C, D, E, F] {
+        record.edit(&mut target, Op::Add(add));
+        if target == "ab" {
+            record.set_saved();
+        }
+    }
+    record.go_to(&mut target, 5);
+    record.set_barrier();
+    record.go_to(&mut target, 6);
+
+    assert!(!record.squash_with(1..7, |edits| Op::Batch(edits.into_iter().collect())));
+    // The head is inside the range.
+    record.undo(&mut target);
+    assert!(!record.squash_with(4.., |edits| Op::Batch(edits.into_iter().collect())));
+    record.redo(&mut target);
+
+    // The saved state is inside the range and the barrier is after it.
+    assert!(record.squash_with(1..4, |edits| Op::Batch(edits.into_iter().collect())));
+    assert_eq!(record.len(), 4);
+    assert_eq!(record.head(), 4);
+    assert_eq!(record.saved(), None);
+    assert_eq!(record.barrier(), Some(3));
+    assert_eq!(target, "abcdef");
+
+    // The barrier is moved to the end of the squashed entry.
+    record.set_saved();
+    assert!(record.squash_with(2..4, |edits| Op::Batch(edits.into_iter().collect())));
+    assert_eq!(record.len(), 3);
+    assert_eq!(record.saved(), Some(3));
+    assert_eq!(record.barrier(), Some(3));
+
+    record.clear_barrier();
+    record.undo(&mut target);
+    assert_eq!(target, "abcd");
+    record.undo(&mut target);
+    assert_eq!(target, "a");
+    record.undo(&mut target);
+    assert_eq!(target, "");
+    record.go_to(&mut target, 3);
+    assert_eq!(target, "abcdef");
+}
+
+#[test]
+fn truncate_front() {
+    let mut target = String::<256>::new();
+    let mut record = Record::<_, 32>::new();
+    record.edit(&mut target, A);
+    let time = std::time::SystemTime::now();
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    record.edit(&mut target, B);
+    record.set_saved();
+    record.edit(&mut target, C);
+    record.edit(&mut target, D);
+    record.undo(&mut target).unwrap();
+
+    assert_eq!(record.prune_before(time), 1);
+    assert_eq!(record.len(), 3);
+    assert_eq!(record.head(), 2);
+    assert_eq!(record.saved(), Some(1));
+
+    // Only the edits before the head can be removed.
+    assert_eq!(record.truncate_front(5), 2);
+    assert_eq!(record.len(), 1);
+    assert_eq!(record.saved(), None);
+    assert!(!record.can_undo());
+    record.redo(&mut target).unwrap();
+    assert_eq!(target, "abcd");
+}
+
+#[test]
+fn clear_redo() {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let mut target = String::<256>::new();
+    let mut record = Record::<_, 32, _>::builder().connect(sender).build();
+    record.edit(&mut target, A);
+    record.edit(&mut target, B);
+    record.set_saved();
+    record.undo(&mut target).unwrap();
+    receiver.try_iter().for_each(drop);
+
+    record.clear_redo();
+    assert_eq!(record.len(), 1);
+    assert_eq!(record.saved(), None);
+    assert!(!record.can_redo());
+    assert_eq!(
+        receiver.try_iter().collect::<Vec<_>>(),
+        [Event::Redo(false)]
+    );
+    assert_eq!(target, "a");
+}
+
+#[test]
+fn on_evict() {
+    use core::cell::Cell;
+    use undo::Evicted;
+
+    let evicted = Cell::new(0);
+    let mut target = String::<256>::new();
+    let mut record = Record::<_, 32>::builder()
+        .limit(2)
+        .on_evict(|entry: Evicted<Add<256>, 32>| {
+            assert!(matches!(entry, Evicted::Entry(_)));
+            evicted.set(evicted.get() + 1);
+        })
+        .build();
+    record.edit(&mut target, A);
+    record.edit(&mut target, B);
+    record.edit(&mut target, C);
+    assert_eq!(evicted.get(), 1);
+
+    record.undo(&mut target).unwrap();
+    record.edit(&mut target, D);
+    assert_eq!(evicted.get(), 2);
+
+    record.undo(&mut target).unwrap();
+    record.clear_redo();
+    record.clear();
+    assert_eq!(evicted.get(), 4);
+    assert_eq!(target, "ab");
+}
+
+#[test]
+fn on_evict_annul() {
+    use core::cell::Cell;
+    use undo::{Evicted, Merged};
+
+    struct Toggle(char);
+
+    impl undo::Edit for Toggle {
+        type Target = String<256>;
+        type Output = ();
+
+        fn edit(&mut self, target: &mut String<256>) {
+            if target.ends_with(self.0) {
+                target.pop();
+            } else {
+                target.push(self.0).unwrap();
+            }
+        }
+
+        fn undo(&mut self, target: &mut String<256>) {
+            self.edit(target);
+        }
+
+        fn merge(&mut self, other: Self) -> Merged<Self> {
+            if self.0 == other.0 {
+                Merged::Annul
+            } else {
+                Merged::No(other)
+            }
+        }
+    }
+
+    let evicted = Cell::new(0);
+    let mut target = String::<256>::new();
+    let mut record = Record::<_, 32>::builder()
+        .on_evict(|_: Evicted<Toggle, 32>| evicted.set(evicted.get() + 1))
+        .build();
+    record.edit(&mut target, Toggle('a'));
+    record.edit(&mut target, Toggle('b'));
+    record.edit(&mut target, Toggle('b'));
+    assert_eq!(target, "a");
+    assert_eq!(record.len(), 1);
+    assert_eq!(evicted.get(), 1);
+
+    // The saved state stops the edits from being merged when they are applied.
+    record.set_saved();
+    record.edit(&mut target, Toggle('a'));
+    assert_eq!(record.len(), 2);
+    assert!(record.squash_with(.., |_| unreachable!()));
+    assert!(record.is_empty());
+    assert_eq!(evicted.get(), 2);
+    assert_eq!(target, "");
+}
+
+#[test]
+fn pin() {
+    struct Text(std::string::String);
+
+    impl undo::Edit for Text {
+        type Target = std::string::String;
+        type Output = ();
+
+        fn edit(&mut self, target: &mut std::string::String) {
+            target.push_str(&self.0);
+        }
+
+        fn undo(&mut self, target: &mut std::string::String) {
+            target.truncate(target.len() - self.0.len());
+        }
+
+        fn merge(&mut self, other: Self) -> undo::Merged<Self> {
+            self.0.push_str(&other.0);
+            undo::Merged::Yes
+        }
+    }
+
+    impl std::fmt::Display for Text {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.write_str(&self.0)
+        }
+    }
+
+    let exceeded = core::cell::Cell::new(false);
+    let mut target = std::string::String::new();
+    let mut record = Record::<_, 32, _>::builder()
+        .limit(3)
+        .connect(|event| exceeded.set(exceeded.get() || event == Event::LimitExceeded))
+        .build();
+    record.edit(&mut target, Text("a".into()));
+    assert!(record.pin(0));
+    assert!(!record.pin(1));
+    // Saving prevents the edits from being merged when they are pushed.
+    for text in ["b", "c", "d"] {
+        record.set_saved();
+        record.edit(&mut target, Text(text.into()));
+    }
+    assert_eq!(target, "abcd");
+    assert_eq!(record.len(), 3);
+    assert!(record.get_entry(0).unwrap().is_pinned());
+    assert!(record
+        .display::<256>()
+        .detailed(false)
+        .to_string()
+        .contains("1 [PINNED] a\n"));
+
+    assert!(!exceeded.get());
+
+    // The oldest unpinned edit is followed by a pinned edit, so nothing can be merged.
+    assert!(record.pin(2));
+    record.set_saved();
+    record.edit(&mut target, Text("e".into()));
+    assert!(exceeded.get());
+    assert_eq!(record.len(), 4);
+    assert_eq!(target, "abcde");
+
+    record.undo(&mut target).unwrap();
+    assert!(record.is_saved());
+    record.undo(&mut target).unwrap();
+    assert_eq!(target, "abc");
+    record.undo(&mut target).unwrap();
+    assert_eq!(target, "a");
+    record.undo(&mut target).unwrap();
+    assert_eq!(target, "");
+}
+
+#[test]
+fn amend() {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let mut target = String::<256>::new();
+    let mut record = Record::<_, 32, _>::builder().connect(sender).build();
+    record.edit(&mut target, A);
+    record.edit(&mut target, B);
+    record.edit(&mut target, C);
+    record.undo(&mut target).unwrap();
+    record.set_saved();
+    receiver.try_iter().for_each(drop);
+
+    // The saved state depended on the amended edit, so it is cleared.
+    record.amend(&mut target, |edit| *edit = D).unwrap();
+    assert_eq!(target, "ad");
+    assert_eq!(record.head(), 2);
+    assert!(!record.is_saved());
+    assert_eq!(
+        receiver.try_iter().collect::<Vec<_>>(),
+        [Event::Saved(false)]
+    );
+
+    // The redo tail is kept.
+    assert_eq!(record.len(), 3);
+    record.redo(&mut target).unwrap();
+    assert_eq!(target, "adc");
+
+    // The saved state before the amended edit is kept.
+    record.go_to(&mut target, 1);
+    record.set_saved();
+    record.go_to(&mut target, 3);
+    record.amend(&mut target, |edit| *edit = E).unwrap();
+    assert_eq!(target, "ade");
+    record.go_to(&mut target, 1);
+    assert!(record.is_saved());
+
+    // The edit behind the barrier can not be amended.
+    record.set_barrier();
+    assert!(record.amend(&mut target, |edit| *edit = F).is_none());
+    assert_eq!(target, "a");
+    record.clear_barrier();
+    assert!(record.amend(&mut target, |edit| *edit = F).is_some());
+    assert_eq!(target, "f");
+}
+
+#[test]
+fn barrier() {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let mut target = String::<256>::new();
+    let mut record = Record::<_, 32, _>::builder().connect(sender).build();
+    record.edit(&mut target, A);
+    record.edit(&mut target, B);
+    receiver.try_iter().for_each(drop);
+
+    record.set_barrier();
+    assert_eq!(record.barrier(), Some(2));
+    assert!(!record.can_undo());
+    assert_eq!(
+        receiver.try_iter().collect::<Vec<_>>(),
+        [Event::Undo(false), Event::Barrier(Some(2))]
+    );
+    assert!(record
+        .display::<256>()
+        .detailed(false)
+        .to_string()
+        .starts_with("2 [HEAD, BARRIER] Add 'b'\n"));
+
+    assert!(record.undo(&mut target).is_none());
+    assert!(record.go_to(&mut target, 1).is_empty());
+    assert!(record.undo_all(&mut target).is_empty());
+    assert!(record.amend(&mut target, |edit| *edit = D).is_none());
+    assert_eq!(target, "ab");
+
+    record.edit(&mut target, C);
+    record.amend(&mut target, |edit| *edit = D).unwrap();
+    assert_eq!(target, "abd");
+    assert_eq!(record.undo_n(&mut target, 5).len(), 1);
+    assert_eq!(target, "ab");
+
+    // The events follow the barrier instead of the start of the record.
+    receiver.try_iter().for_each(drop);
+    record.redo(&mut target).unwrap();
+    record.undo(&mut target).unwrap();
+    assert_eq!(
+        receiver.try_iter().collect::<Vec<_>>(),
+        [
+            Event::Undo(true),
+            Event::Redo(false),
+            Event::Index(3),
+            Event::Undo(false),
+            Event::Redo(true),
+            Event::Index(2),
+        ]
+    );
+
+    record.clear_barrier();
+    assert_eq!(record.undo_all(&mut target).len(), 2);
+    assert_eq!(target, "");
+}
+
+#[test]
+fn barrier_merge() {
+    struct Flip(char);
+
+    impl undo::Edit for Flip {
+        type Target = String<256>;
+        type Output = ();
+
+        fn edit(&mut self, target: &mut String<256>) {
+            target.push(self.0).unwrap();
+        }
+
+        fn undo(&mut self, target: &mut String<256>) {
+            target.pop();
+        }
+
+        fn merge(&mut self, other: Self) -> undo::Merged<Self> {
+            if self.0 == other.0 {
+                undo::Merged::Annul
+            } else {
+                undo::Merged::No(other)
+            }
+        }
+    }
+
+    // The edit behind the barrier is not merged with the new edit.
+    let mut target = String::new();
+    let mut record = Record::<_, 32>::new();
+    record.edit(&mut target, Flip('a'));
+    record.edit(&mut target, Flip('b'));
+    record.set_barrier();
+    record.edit(&mut target, Flip('b'));
+    assert_eq!(target, "abb");
+    assert_eq!(record.len(), 3);
+    assert_eq!(record.validate(), Ok(()));
+
+    let mut target = String::new();
+    let mut history = undo::History::<_, 32>::new();
+    history.edit(&mut target, Flip('a'));
+    history.edit(&mut target, Flip('b'));
+    history.set_barrier();
+    history.edit(&mut target, Flip('b'));
+    assert_eq!(target, "abb");
+    assert_eq!(history.len(), 3);
+    assert_eq!(history.validate(), Ok(()));
+}
+
+#[test]
+fn byte_budget() {
+    struct Paste(&'static str);
+
+    impl undo::Edit for Paste {
+        type Target = std::string::String;
+        type Output = ();
+
+        fn edit(&mut self, target: &mut std::string::String) {
+            target.push_str(self.0);
+        }
+
+        fn undo(&mut self, target: &mut std::string::String) {
+            target.truncate(target.len() - self.0.len());
+        }
+    }
+
+    impl undo::EditSize for Paste {
+        fn size(&self) -> usize {
+            self.0.len()
+        }
+    }
+
+    let mut target = std::string::String::new();
+    let mut record = Record::<_, 32>::builder().byte_budget(8).build();
+    record.edit(&mut target, Paste("abc"));
+    record.edit(&mut target, Paste("def"));
+    assert_eq!(record.total_size(), 6);
+
+    record.edit(&mut target, Paste("ghi"));
+    assert_eq!(record.len(), 2);
+    assert_eq!(record.total_size(), 6);
+
+    // The latest edit is kept even if it does not fit.
+    record.edit(&mut target, Paste("lmnopqrstu"));
+    assert_eq!(record.len(), 1);
+    assert_eq!(record.total_size(), 10);
+    record.undo(&mut target).unwrap();
+    assert_eq!(target, "abcdefghi");
+}
+
+#[test]
+fn max_age() {
+    use std::time::{Duration, SystemTime};
+
+    let mut target = String::<256>::new();
+    let mut record = Record::<_, 32>::builder()
+        .max_age(Duration::from_millis(50))
+        .build();
+    record.edit(&mut target, A);
+    record.edit(&mut target, B);
+    std::thread::sleep(Duration::from_millis(60));
+    record.edit(&mut target, C);
+    assert_eq!(record.len(), 1);
+    assert_eq!(record.enforce_retention(SystemTime::now()), 0);
+
+    record.edit(&mut target, D);
+    let later = SystemTime::now() + Duration::from_secs(1);
+    assert_eq!(record.enforce_retention(later), 2);
+    assert!(record.is_empty());
+    assert_eq!(target, "abcd");
+
+    // The expired edit after the head is removed too.
+    record.edit(&mut target, E);
+    record.undo(&mut target).unwrap();
+    assert_eq!(record.enforce_retention(later), 1);
+    assert!(record.is_empty());
+    assert_eq!(target, "abcd");
+}
+
+#[test]
+fn keyframes() {
+    let mut target = String::<256>::new();
+    let mut record = Record::<_, 32>::builder()
+        .keyframes(Keyframes::<_, 4>::new(2))
+        .build();
+    for edit in [A, B, C, D, E, F] {
+        record.edit(&mut target, edit);
+    }
+    assert_eq!(record.keyframes().unwrap().len(), 3);
+
+    record.go_to(&mut target, 0);
+    assert_eq!(target, "");
+    // Restores the snapshot at 4 and only redoes one edit.
+    assert_eq!(record.go_to(&mut target, 5).len(), 1);
+    assert_eq!(record.head(), 5);
+    assert_eq!(target, "abcde");
+
+    // The snapshots after an amended entry are not used.
+    record.go_to(&mut target, 3);
+    record.amend(&mut target, |edit| *edit = Add('x'));
+    record.go_to(&mut target, 0);
+    record.go_to(&mut target, 6);
+    assert_eq!(target, "abxdef");
+
+    // The snapshots are not used when undoing or redoing a number of steps.
+    assert_eq!(record.undo_n(&mut target, 2).len(), 2);
+    assert_eq!(record.head(), 4);
+    assert_eq!(record.undo_all(&mut target).len(), 4);
+    assert_eq!(record.redo_n(&mut target, 5).len(), 5);
+    assert_eq!(record.redo_all(&mut target).len(), 1);
+    assert_eq!(target, "abxdef");
+
+    // The snapshots follow the states when the oldest edits are removed.
+    let mut target = String::<256>::new();
+    let mut record = Record::<_, 32>::builder()
+        .limit(3)
+        .keyframes(Keyframes::<_, 4>::new(2))
+        .build();
+    for edit in [A, B, C, D] {
+        record.edit(&mut target, edit);
+    }
+    record.go_to(&mut target, 0);
+    assert_eq!(target, "a");
+    assert!(record.go_to(&mut target, 1).is_empty());
+    assert_eq!(target, "ab");
+}
+
+#[test]
+fn replay() {
+    let mut target = String::<256>::new();
+    let mut record = Record::<_, 32>::new();
+    record.edit(&mut target, A);
+    record.edit(&mut target, B);
+    record.edit(&mut target, C);
+    record.set_saved();
+    record.undo(&mut target);
+
+    let mut fresh = String::<256>::new();
+    assert_eq!(record.replay(&mut fresh).len(), 2);
+    assert_eq!(fresh, target);
+    assert_eq!(record.head(), 2);
+    assert!(!record.is_saved());
+    record.redo(&mut target);
+    assert!(record.is_saved());
+
+    // After the oldest edit is removed the target must start in the state after it.
+    let mut target = String::<256>::new();
+    let mut record = Record::<_, 32>::builder().limit(2).build();
+    for edit in [A, B, C] {
+        record.edit(&mut target, edit);
+    }
+    let mut fresh = String::<256>::new();
+    let _ = fresh.push('a');
+    record.replay(&mut fresh);
+    assert_eq!(fresh, target);
+}
+
+#[test]
+#[should_panic(expected = "undo of `Forget` did not restore the target")]
+fn checked() {
+    use core::fmt::{self, Display, Formatter};
+    use undo::{Checked, Edit};
+
+    #[derive(Debug)]
+    struct Forget;
+
+    impl Edit for Forget {
+        type Target = String<256>;
+        type Output = ();
+
+        fn edit(&mut self, target: &mut String<256>) {
+            let _ = target.push('x');
+        }
+
+        fn undo(&mut self, _: &mut String<256>) {}
+    }
+
+    impl Display for Forget {
+        fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+            f.write_str("Forget")
+        }
+    }
+
+    let mut target = String::<256>::new();
+    let mut record = Record::<_, 32>::new();
+    record.edit(&mut target, Checked::new(Forget));
+    record.undo(&mut target);
+}
+
+#[test]
+fn validate() {
+    let mut target = String::<256>::new();
+    let mut record = Record::<_, 32>::builder().limit(2).build();
+    record.edit(&mut target, A);
+    record.edit(&mut target, B);
+    record.set_saved();
+    record.edit(&mut target, C);
+    record.undo(&mut target);
+    record.set_barrier();
+    assert_eq!(record.validate(), Ok(()));
+
+    // Pinned entries can keep the record above its limit.
+    let mut record = Record::<_, 32>::builder().limit(2).build();
+    record.edit(&mut target, A);
+    record.edit(&mut target, B);
+    record.pin(0);
+    record.pin(1);
+    record.edit(&mut target, C);
+    assert_eq!(record.len(), 3);
+    assert_eq!(record.validate(), Ok(()));
+
+    #[cfg(feature = "serde")]
+    {
+        let json = serde_json::to_string(&record).unwrap();
+        let record: Record<Add<256>, 32> = serde_json::from_str(&json).unwrap();
+        assert_eq!(record.len(), 3);
+        assert!(record.get_entry(1).unwrap().is_pinned());
+    }
+    #[cfg(feature = "binary")]
+    {
+        use undo::Codec;
+
+        let mut buf = [0; 256];
+        let len = record.encode(&mut buf).unwrap();
+        let (record, _) = Record::<Add<256>, 32>::decode(&buf[..len]).unwrap();
+        assert_eq!(record.len(), 3);
+        assert!(record.get_entry(1).unwrap().is_pinned());
+    }
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn deserialize_invalid() {
+    let mut target = String::<256>::new();
+    let mut record = Record::<_, 32>::new();
+    record.edit(&mut target, A);
+    record.edit(&mut target, B);
+    record.set_saved();
+
+    let mut json = serde_json::to_value(&record).unwrap();
+    let valid: Record<Add<256>, 32> = serde_json::from_value(json.clone()).unwrap();
+    assert_eq!(valid.head(), 2);
+    assert!(valid.is_saved());
+
+    json["index"] = 3.into();
+    let error = serde_json::from_value::<Record<Add<256>, 32>>(json).unwrap_err();
+    assert_eq!(error.to_string(), "the head is after the last entry");
+}
+
+#[test]
+#[cfg(feature = "binary")]
+fn binary() {
+    use undo::Codec;
+
+    let mut target = String::<256>::new();
+    let mut record = Record::<_, 32>::new();
+    record.edit(&mut target, A);
+    record.edit(&mut target, B);
+    record.edit(&mut target, C);
+    record.undo(&mut target);
+    record.set_saved();
+
+    let mut buf = [0; 256];
+    let len = record.encode(&mut buf).unwrap();
+    assert!(record.encode(&mut buf[..len - 1]).is_none());
+
+    let (mut decoded, read) = Record::<Add<256>, 32>::decode(&buf[..len]).unwrap();
+    assert_eq!(read, len);
+    assert_eq!(decoded.head(), 2);
+    assert!(decoded.is_saved());
+    decoded.redo(&mut target);
+    assert_eq!(target, "abc");
+
+    // The entries do not fit in the capacity.
+    assert!(Record::<Add<256>, 2>::decode(&buf[..len]).is_none());
+    assert!(Record::<Add<256>, 32>::decode(&buf[..len - 1]).is_none());
+}